@@ -0,0 +1,140 @@
+use std::{io::{Read, Write}, path::{Path, PathBuf}};
+
+use anyhow::Result;
+use chrono::prelude::{DateTime, Utc};
+use console::style;
+use serde::{Serialize, Deserialize};
+use vexv5_serial::device::{VexDevice, VexFiletransferFinished, VexVID};
+
+use crate::{error::Error, files::{self, UploadOptions}, output::{self, Message}, system};
+
+
+/// The name of the manifest inside a backup directory
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// The metadata of a single file in a backup
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupEntry {
+    pub name: String,
+    pub vid: u8,
+    pub addr: u32,
+    pub version: u32,
+    pub r#type: String,
+    pub timestamp: u32,
+    pub size: u32,
+    pub crc: u32,
+}
+
+/// Describes everything in a backup
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    /// When the backup was taken, as ISO 8601
+    pub date: String,
+    pub files: Vec<BackupEntry>,
+}
+
+/// Computes the CRC the brain uses for file contents
+fn checksum(data: &[u8]) -> u32 {
+    crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(data)
+}
+
+/// Converts a file type to the null padded form the brain stores
+fn type_bytes(r#type: &str) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    for (b, c) in bytes.iter_mut().zip(r#type.bytes().take(3)) {
+        *b = c;
+    }
+    bytes
+}
+
+/// Downloads every user file on the brain, including the slot INIs, into a backup directory
+pub fn backup<T: Read + Write>(device: &mut VexDevice<T>, output: Option<String>) -> Result<PathBuf> {
+    // Default to a directory named after the current time
+    let now = <DateTime<Utc>>::from(std::time::SystemTime::now());
+    let dir = PathBuf::from(output.unwrap_or_else(|| format!("v5-backup-{}", now.format("%Y%m%d-%H%M%S"))));
+    std::fs::create_dir_all(&dir)?;
+
+    let entries = system::list_files(device, VexVID::USER as u8)?;
+    if !output::is_json() {
+        println!("{} {} files", style("Backing up").bright(), entries.len());
+    }
+
+    let mut manifest = Manifest {
+        date: now.format("%+").to_string(),
+        files: Vec::new(),
+    };
+
+    for entry in entries {
+        let data = files::download_file(device, entry.name.clone())?;
+
+        // Make sure what we got is what the brain has
+        let crc = checksum(&data);
+        if crc != entry.crc {
+            output::warn(&format!("{} has CRC {:08X} but the brain reported {:08X}", entry.name, crc, entry.crc));
+        }
+
+        std::fs::write(dir.join(&entry.name), &data)?;
+
+        let end = entry.r#type.iter().position(|&c| c == 0).unwrap_or(4);
+        manifest.files.push(BackupEntry {
+            name: entry.name,
+            vid: VexVID::USER as u8,
+            addr: entry.addr,
+            version: entry.version,
+            r#type: String::from_utf8_lossy(&entry.r#type[..end]).to_string(),
+            timestamp: entry.timestamp,
+            size: data.len() as u32,
+            crc,
+        });
+    }
+
+    std::fs::write(dir.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)?;
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "backup", data: serde_json::json!({ "dir": dir, "files": manifest.files }) });
+    } else {
+        println!("{} {} {}", output::pass_mark(), style(format!("Backed up {} files to", manifest.files.len())).bold(), style(dir.display()).cyan().bright());
+    }
+
+    Ok(dir)
+}
+
+/// Uploads every file in a backup directory back to the brain, preserving its metadata
+pub fn restore<T: Read + Write>(device: &mut VexDevice<T>, dir: &Path) -> Result<()> {
+    let manifest: Manifest = serde_json::from_str(&std::fs::read_to_string(dir.join(MANIFEST_FILE))?)?;
+
+    // Check the whole backup before uploading anything, so a broken one doesn't restore halfway
+    let mut checked = Vec::new();
+    for entry in &manifest.files {
+        let data = std::fs::read(dir.join(&entry.name))?;
+        let crc = checksum(&data);
+        if crc != entry.crc {
+            return Err(Error::CrcMismatch { file: entry.name.clone(), expected: entry.crc, found: crc }.into());
+        }
+        checked.push((entry, data));
+    }
+
+    if !output::is_json() {
+        println!("{} {} files from {}", style("Restoring").bright(), checked.len(), manifest.date);
+    }
+
+    for (entry, data) in checked {
+        files::upload_file_with(device, entry.name.clone(), data, UploadOptions {
+            finished: VexFiletransferFinished::DoNothing,
+            vid: num::FromPrimitive::from_u8(entry.vid).unwrap_or(VexVID::USER),
+            addr: entry.addr,
+            version: entry.version,
+            r#type: type_bytes(&entry.r#type),
+            timestamp: entry.timestamp,
+            ..Default::default()
+        })?;
+    }
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "restore", data: serde_json::json!({ "dir": dir, "files": manifest.files }) });
+    } else {
+        println!("{} {}", output::pass_mark(), style(format!("Restored {} files", manifest.files.len())).bold());
+    }
+
+    Ok(())
+}
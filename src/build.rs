@@ -0,0 +1,140 @@
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::Result;
+use console::style;
+use indicatif::HumanBytes;
+
+use crate::{config, error::Error, output::{self, Message}, size, target, util};
+
+
+/// The standard library crates built for the brain
+const BUILD_STD: &str = "core,alloc,compiler_builtins";
+
+/// The features compiler_builtins needs without a libc to provide memcpy and friends
+const BUILD_STD_FEATURES: &str = "compiler-builtins-mem,compiler-builtins-mangled-names";
+
+/// Options for building a program for the brain
+#[derive(clap::Args, Debug, Clone)]
+pub struct BuildOptions {
+    /// Build with the dev profile instead of release
+    #[clap(long)]
+    pub debug: bool,
+    /// How many of the largest crates and symbols to show
+    #[clap(long, value_name = "N", default_value_t = size::DEFAULT_TOP)]
+    pub top: usize,
+    /// Extra arguments passed on to `cargo build`, after --
+    #[clap(last = true)]
+    pub args: Vec<String>,
+}
+
+/// The files a build produced
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    pub elf: PathBuf,
+    pub bin: PathBuf,
+}
+
+/// Returns true if the project's cargo config already passes our linker script to the linker
+fn config_links_script() -> bool {
+    std::fs::read_to_string(".cargo/config.toml")
+        .map(|config| config.contains(target::LINKER_SCRIPT_FILE))
+        .unwrap_or(false)
+}
+
+/// Builds the project for the brain and converts it to a .bin ready to upload
+pub fn build(options: &BuildOptions) -> Result<BuildOutput> {
+    let v5 = config::read_cargo_toml()?.v5();
+    let spec = target::find_target_spec(Path::new("."))
+        .ok_or_else(|| Error::InvalidConfig(format!(
+            "Could not find {}. Run `cargo v5 target --update` to write the bundled target spec.", target::TARGET_SPEC_FILE
+        )))?;
+    target::warn_if_outdated(Path::new("."));
+
+    // Cargo tells subcommands where it is
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut command = Command::new(cargo);
+    command.arg("build")
+        .arg("--target").arg(&spec)
+        .arg(format!("-Zbuild-std={}", BUILD_STD))
+        .arg(format!("-Zbuild-std-features={}", BUILD_STD_FEATURES))
+        .arg("--message-format=json-render-diagnostics");
+    if !options.debug {
+        command.arg("--release");
+    }
+
+    // Projects set up by hand may have the linker script without telling rustc about it
+    let linker_script = Path::new(target::TARGET_DIR).join(target::LINKER_SCRIPT_FILE);
+    if linker_script.exists() && !config_links_script() {
+        let name = spec.file_stem().unwrap_or_default().to_string_lossy();
+        command.arg("--config").arg(format!(
+            "target.{}.rustflags=[\"-C\", \"link-arg=-T{}\"]", name, linker_script.display()
+        ));
+    }
+    command.args(&options.args);
+
+    // Cargo's messages come on stdout, and the rendered diagnostics on stderr
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow::anyhow!("Could not read cargo's output"))?;
+
+    // The last executable built is the program
+    let mut elf = None;
+    for line in std::io::BufReader::new(stdout).lines() {
+        let message: serde_json::Value = match serde_json::from_str(&line?) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if message["reason"] == "compiler-artifact" {
+            if let Some(executable) = message["executable"].as_str() {
+                elf = Some(PathBuf::from(executable));
+            }
+        }
+    }
+
+    if !child.wait()?.success() {
+        return Err(anyhow::anyhow!("cargo build failed"));
+    }
+    let elf = elf.ok_or_else(|| anyhow::anyhow!("cargo build did not produce a program. Is there a binary target?"))?;
+
+    // Convert it to the flat binary the brain runs
+    let bin = PathBuf::from(util::objcopy(&elf.to_string_lossy())?);
+    let bin_size = std::fs::metadata(&bin)?.len();
+    let sections = size::sections(&elf)?;
+    let symbols = size::symbols(&elf)?;
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "build", data: serde_json::json!({
+            "elf": elf,
+            "bin": bin,
+            "size": bin_size,
+            "budget": v5.max_size.as_ref().map(|limit| limit.bytes()).transpose()?,
+            "sections": sections,
+            "crates": size::crates(&symbols).into_iter().take(options.top)
+                .map(|(name, size)| serde_json::json!({ "crate": name, "size": size }))
+                .collect::<Vec<_>>(),
+            "symbols": &symbols[..symbols.len().min(options.top)],
+        }) });
+    } else {
+        size::print_sections(&sections);
+        if options.top > 0 {
+            size::print_largest(&symbols, options.top);
+            println!();
+        }
+        size::print_budget(bin_size, &v5)?;
+    }
+
+    // Fail the build, like the hook would fail the upload
+    size::check_budget(&bin.to_string_lossy(), bin_size, &v5)?;
+
+    if !output::is_json() {
+        println!("{} {} {} {}",
+            output::pass_mark(),
+            style("Built").bold(),
+            style(bin.display()).cyan().bright(),
+            style(format!("({})", HumanBytes(bin_size))).bold()
+        );
+    }
+
+    Ok(BuildOutput { elf, bin })
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use crate::{error::Error, files::AfterUpload, firmware::FirmwarePolicy, size::{SizeLimit, SizePolicy}};
+
+
+/// Settings for cargo-v5, read from the `[package.metadata.v5]` table of a project's Cargo.toml
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct V5Metadata {
+    /// What the brain should do once the program has been uploaded
+    pub after_upload: Option<AfterUpload>,
+    /// Whether to open the terminal after uploading
+    pub terminal: Option<bool>,
+    /// The program icon, by name or number
+    pub icon: Option<String>,
+    /// The device expected on each smart port, keyed by port number
+    pub ports: Option<BTreeMap<String, String>>,
+    /// The oldest VEXos version the program can run on
+    pub min_vexos: Option<String>,
+    /// The oldest controller firmware that can be uploaded through
+    pub min_controller_firmware: Option<String>,
+    /// Whether old firmware should stop an upload or just warn
+    pub firmware_policy: Option<FirmwarePolicy>,
+    /// The largest the program's .bin may be
+    pub max_size: Option<SizeLimit>,
+    /// Whether a program over max-size should stop an upload or just warn
+    pub size_policy: Option<SizePolicy>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Metadata {
+    pub v5: Option<V5Metadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub metadata: Option<Metadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CargoToml {
+    pub package: Package,
+}
+
+impl CargoToml {
+    /// Returns the cargo-v5 settings for this project, or the defaults if there are none
+    pub fn v5(&self) -> V5Metadata {
+        self.package.metadata.as_ref()
+            .and_then(|m| m.v5.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Reads the Cargo.toml in the current directory
+pub fn read_cargo_toml() -> Result<CargoToml> {
+    // Try to find a Cargo.toml in the current directory
+    let cargo = std::path::Path::new("./Cargo.toml");
+
+    // If we can't find it, then we can't upload
+    if !cargo.exists() {
+        return Err(Error::InvalidConfig("Could not find Cargo.toml in the current directory".to_string()).into());
+    }
+
+    // Parse the toml file
+    let f = std::fs::read_to_string(cargo)?;
+    toml::from_str::<CargoToml>(&f)
+        .map_err(|e| Error::InvalidConfig(format!("Could not parse Cargo.toml: {}", e)).into())
+}
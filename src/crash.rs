@@ -0,0 +1,245 @@
+use std::path::{Path, PathBuf};
+
+use addr2line::object::Object;
+use anyhow::Result;
+use console::style;
+
+
+/// Lines that mark the start of a crash report
+const CRASH_MARKERS: [&str; 4] = [
+    "panicked at",
+    "DATA ABORT EXCEPTION",
+    "PREFETCH ABORT EXCEPTION",
+    "UNDEFINED INSTRUCTION EXCEPTION",
+];
+
+/// A single symbolized stack frame.
+/// One address may produce several frames when functions were inlined.
+#[derive(Clone, Debug, Default)]
+pub struct Frame {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+/// Looks up the function, file and line of each address using the ELF's debug info.
+/// Falls back to the symbol table when there is no DWARF for an address.
+pub fn symbolize(elf: &Path, addresses: &[u32]) -> Result<Vec<Vec<Frame>>> {
+    // Parse the ELF and its debug info
+    let data = std::fs::read(elf)?;
+    let object = addr2line::object::File::parse(&*data)?;
+    let context = addr2line::Context::new(&object)?;
+    let symbols = object.symbol_map();
+
+    let mut result = Vec::new();
+    for &address in addresses {
+        let mut frames = Vec::new();
+
+        // Walk the inlined frames at this address
+        let mut iter = context.find_frames(address.into())?;
+        while let Some(frame) = iter.next()? {
+            frames.push(Frame {
+                function: match frame.function {
+                    Some(function) => Some(function.demangle()?.to_string()),
+                    None => None,
+                },
+                file: frame.location.as_ref().and_then(|l| l.file).map(|f| f.to_string()),
+                line: frame.location.as_ref().and_then(|l| l.line),
+                column: frame.location.as_ref().and_then(|l| l.column),
+            });
+        }
+
+        // Without debug info we can at least name the function
+        if frames.iter().all(|f| f.function.is_none()) {
+            if let Some(symbol) = symbols.get(address.into()) {
+                let name = addr2line::demangle_auto(symbol.name().into(), None).to_string();
+                match frames.first_mut() {
+                    Some(frame) => frame.function = Some(name),
+                    None => frames.push(Frame { function: Some(name), ..Default::default() }),
+                }
+            }
+        }
+
+        result.push(frames);
+    }
+
+    Ok(result)
+}
+
+/// Parses an address printed in a crash report, with or without a 0x prefix
+fn parse_address(text: &str) -> Option<u32> {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    u32::from_str_radix(text, 16).ok()
+}
+
+/// Returns the addresses to look up for each frame of a stack trace.
+/// Every frame but the first holds a return address, which points after the call.
+/// Looking up the byte before it finds the call itself, even when the call was the
+/// last instruction of a function or of an inlined block.
+fn lookup_addresses(trace: &[u32]) -> Vec<u32> {
+    trace.iter().enumerate()
+        .map(|(i, &address)| if i > 0 { address.saturating_sub(1) } else { address })
+        .collect()
+}
+
+/// Watches program output for panic and abort reports and prints a symbolized backtrace for them.
+///
+/// Stack traces are expected in the same format PROS uses:
+/// ```text
+/// PC: 3800ab4
+/// BEGIN STACK TRACE
+///     3800ab4
+///     3801234
+/// END OF TRACE
+/// ```
+pub struct CrashDecoder {
+    /// The ELF file of the running program
+    elf: PathBuf,
+    /// The partial line currently being received
+    line: Vec<u8>,
+    /// The addresses of the stack trace being received, if we are inside one
+    trace: Option<Vec<u32>>,
+}
+
+impl CrashDecoder {
+    pub fn new(elf: impl Into<PathBuf>) -> Self {
+        Self {
+            elf: elf.into(),
+            line: Vec::new(),
+            trace: None,
+        }
+    }
+
+    /// Feeds a chunk of program output into the decoder
+    pub fn feed(&mut self, data: &[u8]) {
+        for &byte in data {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&self.line).trim().to_string();
+            self.line.clear();
+            self.handle_line(&line);
+        }
+    }
+
+    /// Handles a single line of program output
+    fn handle_line(&mut self, line: &str) {
+        // Collect addresses while inside a stack trace
+        if let Some(trace) = &mut self.trace {
+            if line == "END OF TRACE" {
+                let trace = std::mem::take(trace);
+                self.trace = None;
+                self.print_backtrace(&trace);
+            } else if let Some(address) = parse_address(line) {
+                trace.push(address);
+            }
+            return;
+        }
+
+        if CRASH_MARKERS.iter().any(|marker| line.contains(marker)) {
+            eprintln!("{} {}", style("Program crashed:").red().bold(), line);
+        } else if let Some(pc) = line.strip_prefix("PC:").and_then(parse_address) {
+            // Show where the abort happened right away in case no trace follows
+            eprintln!("{} {}", style("Crashed at").red().bold(), self.describe(pc));
+        } else if line == "BEGIN STACK TRACE" {
+            self.trace = Some(Vec::new());
+        }
+    }
+
+    /// Describes a single address as its outermost function and location
+    fn describe(&self, address: u32) -> String {
+        match symbolize(&self.elf, &[address]) {
+            Ok(frames) => match frames.first().and_then(|f| f.last()) {
+                Some(frame) => format!("{:#010x} - {}", address, format_frame(frame)),
+                None => format!("{:#010x} - <unknown>", address),
+            },
+            Err(e) => format!("{:#010x} (could not symbolize: {})", address, e),
+        }
+    }
+
+    /// Prints a readable backtrace in the same style as Rust's own
+    fn print_backtrace(&self, trace: &[u32]) {
+        let frames = match symbolize(&self.elf, &lookup_addresses(trace)) {
+            Ok(frames) => frames,
+            Err(e) => {
+                eprintln!("{} could not symbolize backtrace: {}", style("warning:").yellow().bold(), e);
+                return;
+            }
+        };
+
+        eprintln!("{}", style("stack backtrace:").bold());
+        for (i, (address, frames)) in trace.iter().zip(frames).enumerate() {
+            if frames.is_empty() {
+                eprintln!("{:>4}: {:#010x} - <unknown>", i, address);
+                continue;
+            }
+
+            for (j, frame) in frames.iter().enumerate() {
+                // Only label the first of a group of inlined frames with the address
+                if j == 0 {
+                    eprintln!("{:>4}: {:#010x} - {}", i, address, style(frame.function.as_deref().unwrap_or("<unknown>")).cyan());
+                } else {
+                    eprintln!("{:>18} {}", "", style(frame.function.as_deref().unwrap_or("<unknown>")).cyan());
+                }
+                if let Some(file) = &frame.file {
+                    eprintln!("{:>18} at {}:{}:{}", "", file, frame.line.unwrap_or(0), frame.column.unwrap_or(0));
+                }
+            }
+        }
+    }
+}
+
+/// Formats a frame as `function at file:line:column`
+fn format_frame(frame: &Frame) -> String {
+    let function = frame.function.as_deref().unwrap_or("<unknown>");
+    match &frame.file {
+        Some(file) => format!("{} at {}:{}:{}", function, file, frame.line.unwrap_or(0), frame.column.unwrap_or(0)),
+        None => function.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(parse_address("3800ab4"), Some(0x3800ab4));
+        assert_eq!(parse_address("  0x3801234 "), Some(0x3801234));
+        assert_eq!(parse_address("END OF TRACE"), None);
+        assert_eq!(parse_address("123456789"), None);
+    }
+
+    #[test]
+    fn looks_up_calls_for_return_addresses() {
+        assert_eq!(lookup_addresses(&[0x3800ab4, 0x3801234, 0x3801000]), [0x3800ab4, 0x3801233, 0x3800fff]);
+        assert_eq!(lookup_addresses(&[]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn collects_trace_between_markers() {
+        let mut decoder = CrashDecoder::new("missing.elf");
+        decoder.feed(b"hello\nBEGIN STACK TRACE\n    3800ab4\n");
+        decoder.feed(b"    0x380");
+        decoder.feed(b"1234\r\nnot an address\n");
+
+        assert_eq!(decoder.trace, Some(vec![0x3800ab4, 0x3801234]));
+
+        // The trace is printed and forgotten once it ends, even if the ELF can't be read
+        decoder.feed(b"END OF TRACE\n");
+        assert_eq!(decoder.trace, None);
+    }
+
+    #[test]
+    fn ignores_output_outside_trace() {
+        let mut decoder = CrashDecoder::new("missing.elf");
+        decoder.feed(b"3800ab4\nEND OF TRACE\n");
+
+        assert_eq!(decoder.trace, None);
+        assert!(decoder.line.is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::Path;
+
+use anyhow::Result;
+use console::style;
+use serde::Serialize;
+
+use crate::{output::{self, Message}, target::{self, FileState}, util};
+
+
+/// The USB vendor id of VEX Robotics
+pub const VEX_VENDOR_ID: u16 = 0x2888;
+
+/// Where udev rules are written if no path is given
+pub const DEFAULT_UDEV_RULES_PATH: &str = "/etc/udev/rules.d/99-vex.rules";
+
+/// The udev rules that let any logged in user open VEX devices
+const UDEV_RULES: &str = r#"# VEX V5 brains and controllers, installed by cargo-v5
+SUBSYSTEMS=="usb", ATTRS{idVendor}=="2888", MODE="0666", TAG+="uaccess"
+KERNEL=="ttyACM[0-9]*", ATTRS{idVendor}=="2888", MODE="0666", TAG+="uaccess", ENV{ID_MM_DEVICE_IGNORE}="1"
+"#;
+
+/// Options for the environment checks
+#[derive(clap::Args, Debug, Clone)]
+pub struct DoctorOptions {
+    /// Write udev rules for VEX devices, to /etc/udev/rules.d/99-vex.rules unless another path is given
+    #[clap(long, value_name = "PATH", num_args = 0..=1, default_missing_value = DEFAULT_UDEV_RULES_PATH)]
+    pub write_udev_rules: Option<String>,
+}
+
+/// How a check turned out
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The result of checking one part of the environment
+#[derive(Serialize, Clone, Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to do about a warning or failure
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: Status::Pass, detail: detail.into(), hint: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Warn, detail: detail.into(), hint: Some(hint.into()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: Status::Fail, detail: detail.into(), hint: Some(hint.into()) }
+    }
+}
+
+/// Tries to open every connected VEX device
+fn check_ports() -> Vec<Check> {
+    let pairs = match util::discover_pairs() {
+        Ok(pairs) => pairs,
+        Err(_) => return vec![Check::warn("serial ports", "No VEX devices are connected", "Plug in a brain or controller to check that its ports can be opened.")],
+    };
+
+    pairs.into_iter().map(|pair| {
+        let label = util::describe_ports(&pair);
+        match util::prepare_device(pair) {
+            Ok(_) => Check::pass("serial ports", format!("Opened {}", label)),
+            Err(e) => Check::fail("serial ports", format!("Could not open {}: {}", label, e), if cfg!(target_os = "linux") {
+                "Run `cargo v5 doctor --write-udev-rules` as root, then unplug the device and plug it back in."
+            } else {
+                "Close any other program that is using the device."
+            }),
+        }
+    }).collect()
+}
+
+/// Returns the names of the groups the current user is in
+#[cfg(target_os = "linux")]
+fn user_groups() -> Option<Vec<String>> {
+    let output = std::process::Command::new("id").arg("-Gn").output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).split_whitespace().map(|g| g.to_string()).collect())
+}
+
+/// Checks whether the user is in a group that owns serial ports.
+/// Not needed with udev rules installed, so this only warns.
+#[cfg(target_os = "linux")]
+fn check_groups() -> Check {
+    // Distributions disagree on the group's name
+    const SERIAL_GROUPS: [&str; 2] = ["dialout", "uucp"];
+
+    match user_groups() {
+        Some(groups) => match groups.iter().find(|g| SERIAL_GROUPS.contains(&g.as_str())) {
+            Some(group) => Check::pass("groups", format!("You are in the {} group", group)),
+            None => Check::warn(
+                "groups",
+                "You are not in the dialout or uucp group",
+                "Install the udev rules, or run `sudo usermod -aG dialout $USER` and log in again.",
+            ),
+        },
+        None => Check::warn("groups", "Could not list your groups", "Make sure the `id` command is available."),
+    }
+}
+
+/// Checks whether udev rules for VEX devices are installed
+#[cfg(target_os = "linux")]
+fn check_udev_rules() -> Check {
+    let vendor = format!("{:04x}", VEX_VENDOR_ID);
+
+    let installed = ["/etc/udev/rules.d", "/lib/udev/rules.d", "/usr/lib/udev/rules.d"].iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .find(|entry| std::fs::read_to_string(entry.path()).map(|rules| rules.contains(&vendor)).unwrap_or(false));
+
+    match installed {
+        Some(entry) => Check::pass("udev rules", format!("Found {}", entry.path().display())),
+        None => Check::warn("udev rules", "No udev rules for VEX devices are installed", "Run `cargo v5 doctor --write-udev-rules` as root."),
+    }
+}
+
+/// Checks that the ARM toolchain is on the PATH
+fn check_toolchain() -> Check {
+    match std::process::Command::new("arm-none-eabi-objcopy").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).lines().next().unwrap_or_default().to_string();
+            Check::pass("toolchain", version)
+        },
+        _ => Check::fail(
+            "toolchain",
+            "arm-none-eabi-objcopy was not found",
+            "Install the GNU Arm Embedded toolchain (arm-none-eabi) and make sure it is on your PATH.",
+        ),
+    }
+}
+
+/// Checks that the project has a target spec, and that it matches the bundled one
+fn check_target_spec() -> Check {
+    if !Path::new("Cargo.toml").exists() {
+        return Check::warn("target spec", "Not in a cargo project", "Run the doctor from your project's directory to check its target spec.");
+    }
+
+    let files = target::project_files(Path::new("."));
+    if let Some(missing) = files.iter().find(|f| f.state == FileState::Missing) {
+        return Check::fail(
+            "target spec",
+            format!("Could not find {}", missing.path.display()),
+            "Run `cargo v5 target --update` to write the target spec and linker script bundled with cargo-v5.",
+        );
+    }
+    match files.iter().find(|f| f.state == FileState::Outdated) {
+        Some(outdated) => Check::warn(
+            "target spec",
+            format!("{} differs from the one bundled with cargo-v5", outdated.path.display()),
+            "Run `cargo v5 target --update` to replace it.",
+        ),
+        None => Check::pass("target spec", format!("Found {}", files[0].path.display())),
+    }
+}
+
+/// Writes the udev rules for VEX devices
+fn write_udev_rules(path: &str) -> Result<()> {
+    std::fs::write(path, UDEV_RULES)
+        .map_err(|e| anyhow::anyhow!(e).context(format!("Could not write {}. Writing to /etc usually needs root.", path)))?;
+
+    if !output::is_json() {
+        println!("{} {} {}", output::pass_mark(), style("Wrote udev rules to").bold(), style(path).cyan().bright());
+        println!("{}", style("Run `sudo udevadm control --reload-rules && sudo udevadm trigger`, then plug your devices back in.").black().bright());
+    }
+
+    Ok(())
+}
+
+/// Checks that this machine is ready to build and upload programs.
+/// Returns true if nothing failed.
+pub fn doctor(options: &DoctorOptions) -> Result<bool> {
+    if let Some(path) = &options.write_udev_rules {
+        write_udev_rules(path)?;
+    }
+
+    let mut checks = check_ports();
+    #[cfg(target_os = "linux")]
+    {
+        checks.push(check_groups());
+        checks.push(check_udev_rules());
+    }
+    checks.push(check_toolchain());
+    checks.push(check_target_spec());
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "doctor", data: serde_json::to_value(&checks)? });
+    } else {
+        for check in &checks {
+            let mark = match check.status {
+                Status::Pass => output::pass_mark(),
+                Status::Warn => output::warn_mark(),
+                Status::Fail => output::fail_mark(),
+            };
+            println!("{} {:<14}{}", mark, style(check.name).bold(), check.detail);
+            if let Some(hint) = &check.hint {
+                println!("  {:<14}{}", "", style(hint).black().bright());
+            }
+        }
+    }
+
+    Ok(checks.iter().all(|c| c.status != Status::Fail))
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+use console::style;
+use vexv5_serial::errors::VexDeviceError;
+
+
+/// The errors cargo-v5 knows how to explain, each with its own exit status.
+/// Anything else is reported as a plain error with status 1.
+///
+/// The exit statuses follow sysexits.h, so they don't collide with the statuses
+/// the terminal and test runner pass on from the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No vex devices are plugged in
+    DeviceNotFound,
+    /// We are not allowed to open a serial port
+    PermissionDenied { port: Option<String> },
+    /// The device stopped answering
+    Timeout,
+    /// The brain refused a command
+    Nack { code: u8 },
+    /// A file's contents don't match its CRC
+    CrcMismatch { file: String, expected: u32, found: u32 },
+    /// The project config or a value from it is invalid
+    InvalidConfig(String),
+    /// A tool we need is not installed
+    ToolchainMissing { tool: String },
+    /// The brain or controller firmware is older than the project requires
+    FirmwareTooOld { problems: Vec<String> },
+    /// The program is bigger than the project's size budget
+    TooLarge { file: String, size: u64, budget: u64 },
+}
+
+impl Error {
+    /// A short name for the kind of error, used in JSON messages
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::DeviceNotFound => "device-not-found",
+            Error::PermissionDenied { .. } => "permission-denied",
+            Error::Timeout => "timeout",
+            Error::Nack { .. } => "nack",
+            Error::CrcMismatch { .. } => "crc-mismatch",
+            Error::InvalidConfig(_) => "invalid-config",
+            Error::ToolchainMissing { .. } => "toolchain-missing",
+            Error::FirmwareTooOld { .. } => "firmware-too-old",
+            Error::TooLarge { .. } => "too-large",
+        }
+    }
+
+    /// The status cargo-v5 exits with when this error stops it
+    pub fn exit_status(&self) -> i32 {
+        match self {
+            Error::DeviceNotFound => 69, // EX_UNAVAILABLE
+            Error::PermissionDenied { .. } => 77, // EX_NOPERM
+            Error::Timeout => 75, // EX_TEMPFAIL
+            Error::Nack { .. } => 76, // EX_PROTOCOL
+            Error::CrcMismatch { .. } => 65, // EX_DATAERR
+            Error::InvalidConfig(_) => 78, // EX_CONFIG
+            Error::ToolchainMissing { .. } => 72, // EX_OSFILE
+            Error::FirmwareTooOld { .. } => 71, // EX_OSERR
+            Error::TooLarge { .. } => 73, // EX_CANTCREAT
+        }
+    }
+
+    /// What the user can do about the error
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Error::DeviceNotFound => Some("Plug in a brain or controller over USB and make sure it is turned on."),
+            Error::PermissionDenied { .. } if cfg!(target_os = "linux") => Some(
+                "Your user is not allowed to open the serial port. Install udev rules for VEX devices, \
+                or add yourself to the group that owns the port (usually dialout) and log in again."
+            ),
+            Error::PermissionDenied { .. } => Some("Close any other program that is using the serial port and try again."),
+            Error::Timeout => Some("Check the cable, or move the controller closer to the brain if you are connected wirelessly."),
+            Error::Nack { .. } => Some("Try again. If it keeps failing, restart the brain."),
+            Error::CrcMismatch { .. } => Some("The data was corrupted in transfer or on disk. Try again."),
+            Error::InvalidConfig(_) => Some("Check the [package.metadata.v5] table in your Cargo.toml."),
+            Error::ToolchainMissing { .. } => Some("Install the GNU Arm Embedded toolchain (arm-none-eabi) and make sure it is on your PATH."),
+            Error::FirmwareTooOld { .. } => Some("Update the brain with `cargo v5 firmware <PACKAGE>`, or pass --ignore-firmware to upload anyway."),
+            Error::TooLarge { .. } => Some(
+                "Run `cargo v5 build` to see the largest symbols and crates, \
+                or raise max-size in the [package.metadata.v5] table of your Cargo.toml."
+            ),
+        }
+    }
+
+    /// Finds the error we know how to explain behind a failure, if there is one
+    pub fn classify(error: &anyhow::Error) -> Option<Error> {
+        error.chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<Error>() {
+                return Some(e.clone());
+            }
+
+            if let Some(VexDeviceError::NACK(ack)) = cause.downcast_ref::<VexDeviceError>() {
+                return Some(Error::Nack { code: *ack as u8 });
+            }
+
+            let io = match cause.downcast_ref::<serialport::Error>() {
+                Some(serialport::Error { kind: serialport::ErrorKind::Io(kind), .. }) => Some(*kind),
+                _ => cause.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+            };
+            match io {
+                Some(std::io::ErrorKind::PermissionDenied) => Some(Error::PermissionDenied { port: None }),
+                Some(std::io::ErrorKind::TimedOut) => Some(Error::Timeout),
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Describes a NACK code the way the V5 protocol names it
+fn nack_reason(code: u8) -> &'static str {
+    match code {
+        0xCE => "CRC error",
+        0xD0 => "payload too short",
+        0xD1 => "transfer too large",
+        0xD2 => "program CRC error",
+        0xD3 => "program file error",
+        0xD4 => "no transfer in progress",
+        0xD5 => "invalid transfer",
+        0xD6 => "data not aligned",
+        0xD7 => "address mismatch",
+        0xD8 => "download length mismatch",
+        0xD9 => "file does not exist",
+        0xDA => "no room for more files",
+        0xDB => "file already exists",
+        _ => "general failure",
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DeviceNotFound => write!(f, "No Vex devices found"),
+            Error::PermissionDenied { port: Some(port) } => write!(f, "Permission denied opening {}", port),
+            Error::PermissionDenied { port: None } => write!(f, "Permission denied opening the serial port"),
+            Error::Timeout => write!(f, "The device did not respond in time"),
+            Error::Nack { code } => write!(f, "The brain refused the command: {} ({:#04x})", nack_reason(*code), code),
+            Error::CrcMismatch { file, expected, found } => write!(f, "{} has CRC {:08X} but {:08X} was expected", file, found, expected),
+            Error::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            Error::ToolchainMissing { tool } => write!(f, "Could not find {}", tool),
+            Error::FirmwareTooOld { problems } => write!(f, "Firmware is older than this project requires. {}", problems.join(" ")),
+            Error::TooLarge { file, size, budget } => write!(f, "{} is {} bytes, over the budget of {} bytes", file, size, budget),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Prints an error with its causes and, if we know one, what to do about it
+pub fn print_error(error: &anyhow::Error, known: Option<&Error>) {
+    eprintln!("{} {}", style("Error:").red().bright(), error);
+    for cause in error.chain().skip(1) {
+        eprintln!("  {} {}", style("Caused by:").black().bright(), cause);
+    }
+    if let Some(hint) = known.and_then(Error::hint) {
+        eprintln!("  {} {}", style("Hint:").cyan().bright(), hint);
+    }
+}
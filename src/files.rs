@@ -1,88 +1,173 @@
-use std::{io::{Read, Write}, time::Duration};
+use std::{io::{Read, Write}, sync::Arc, time::Duration};
 
-use console::style;
 use dialoguer::Confirm;
-use indicatif::{ProgressBar, HumanBytes, HumanDuration};
-use vexv5_serial::device::{VexDevice, VexProduct, V5DeviceVersion};
+use indicatif::{HumanBytes, HumanDuration};
+use vexv5_serial::device::{VexDevice, VexProduct, V5DeviceVersion, VexFileTarget, VexFiletransferFinished, VexVID};
 use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use crate::{progress::{self, Direction, Reporter, TransferEvent}, radio, util};
+
+
+/// What the brain should do once a file has been uploaded
+#[derive(Serialize, Deserialize, clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AfterUpload {
+    /// Leave the brain as it is
+    None,
+    /// Show the program's run screen
+    ShowRunScreen,
+    /// Run the program immediately
+    Run,
+    /// Stop whatever is running
+    Halt,
+}
 
-use crate::util;
+impl From<AfterUpload> for VexFiletransferFinished {
+    fn from(action: AfterUpload) -> Self {
+        match action {
+            AfterUpload::None => VexFiletransferFinished::DoNothing,
+            AfterUpload::ShowRunScreen => VexFiletransferFinished::ShowRunScreen,
+            AfterUpload::Run => VexFiletransferFinished::RunProgram,
+            AfterUpload::Halt => VexFiletransferFinished::Halt,
+        }
+    }
+}
 
-/// Writes data to a file on the V5 brain.
-pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, data: Vec<u8>) -> Result<()> {
+/// Where and how an uploaded file is stored on the brain
+pub struct UploadOptions {
+    /// Where the file is stored
+    pub target: VexFileTarget,
+    /// What the brain should do once the transfer is finished
+    pub finished: VexFiletransferFinished,
+    /// The vendor the file belongs to
+    pub vid: VexVID,
+    /// The address the file is loaded at
+    pub addr: u32,
+    /// The file's version
+    pub version: u32,
+    /// The file's type
+    pub r#type: [u8; 4],
+    /// The file's timestamp
+    pub timestamp: u32,
+    /// Where to report the transfer's progress. Defaults to the console.
+    pub reporter: Option<Arc<dyn Reporter>>,
+    /// Whether to ask before a large transfer through a controller.
+    /// Off by default, so the library never waits on stdin.
+    pub interactive: bool,
+}
+
+impl Default for UploadOptions {
+    /// A user program in flash that shows its run screen once uploaded
+    fn default() -> Self {
+        Self {
+            target: VexFileTarget::FLASH,
+            finished: VexFiletransferFinished::ShowRunScreen,
+            vid: VexVID::USER,
+            addr: 0x3800000,
+            version: 0x01000000,
+            r#type: *b"bin\0",
+            timestamp: 0,
+            reporter: None,
+            interactive: false,
+        }
+    }
+}
 
+/// How a file is read from the brain
+#[derive(Default)]
+pub struct DownloadOptions {
+    /// Where to report the transfer's progress. Defaults to the console.
+    pub reporter: Option<Arc<dyn Reporter>>,
+    /// Whether to ask before a large transfer through a controller.
+    /// Off by default, so the library never waits on stdin.
+    pub interactive: bool,
+}
+
+/// Asks the user whether to go ahead with a large transfer over the radio.
+/// Returns true straight away for small files and devices connected over USB.
+fn confirm_wireless<T: Read + Write>(device: &mut VexDevice<T>, size: u64, verb: &str) -> Result<bool> {
     // If the file size is too large (lets say 16 KiB) and the device is a controller
-    // then prompt before downloading.
+    // then prompt before transferring.
     if let V5DeviceVersion{system_version: _, product_type: VexProduct::V5Controller(_) } = device.get_device_version()? {
-        if data.len() > 64000 {
+        if size > 64000 {
             let prompt = format!(
-                "You are uploading a large ({}) file wirelessly. This is projected to take {} to complete. Are you sure you want to continue?",
-                HumanBytes(data.len() as u64),
-                HumanDuration(Duration::from_secs(data.len() as u64 / 1024)) // The average download speed at close range is ~1 KiB/s
+                "You are {} a large ({}) file wirelessly. This is projected to take {} to complete. Are you sure you want to continue?",
+                verb,
+                HumanBytes(size),
+                HumanDuration(Duration::from_secs(size / 1024)) // The average download speed at close range is ~1 KiB/s
             );
-            if Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default()).with_prompt(prompt).interact()? {
-                // Continue
-            } else {
-                // Abort
-                return Ok(());
-            }
+            return Ok(Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default()).with_prompt(prompt).interact()?);
         }
     }
 
+    Ok(true)
+}
+
+/// Writes data to a file on the V5 brain.
+pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, data: Vec<u8>) -> Result<()> {
+    upload_file_with(device, file_name, data, UploadOptions::default())
+}
+
+/// Writes data to a file on the V5 brain, choosing where and how it is stored
+/// and what the brain should do once the transfer is finished.
+pub fn upload_file_with<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, data: Vec<u8>, options: UploadOptions) -> Result<()> {
+    radio::with_download_channel(device, |device| upload_file_inner(device, file_name, data, options))
+}
+
+fn upload_file_inner<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, data: Vec<u8>, options: UploadOptions) -> Result<()> {
+
+    if options.interactive && !confirm_wireless(device, data.len() as u64, "uploading")? {
+        return Err(anyhow::anyhow!("Aborted upload due to user request"));
+    }
+
     // Begin timer
     let time = std::time::SystemTime::now();
 
-    println!("{} {}", style("Uploading File").bright(), style(file_name.clone()).cyan().bright());
+    let reporter = options.reporter.unwrap_or_else(progress::default_reporter);
+    let size = data.len() as u64;
+    let crc = crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data);
+
+    reporter.report(&TransferEvent::Started { file: &file_name, direction: Direction::Upload, size });
 
     // Write to the slot_1.ini file on the brain
     let mut fh = device.open(file_name.clone(), Some(vexv5_serial::device::VexInitialFileMetadata {
-        function: vexv5_serial::device::VexFileMode::Upload(vexv5_serial::device::VexFileTarget::FLASH, true),
-        vid: vexv5_serial::device::VexVID::USER,
+        function: vexv5_serial::device::VexFileMode::Upload(options.target, true),
+        vid: options.vid,
         options: 0,
         length: data.len() as u32,
-        addr: 0x3800000,
-        crc: crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data),
-        r#type: *b"bin\0",
-        timestamp: 0,
-        version: 0x01000000,
+        addr: options.addr,
+        crc,
+        r#type: options.r#type,
+        timestamp: options.timestamp,
+        version: options.version,
         linked_name: None,
     }))?;
 
     
 
     // Write data
-    util::write_file_progress(&mut fh, data)?;
+    util::write_file_progress(&mut fh, data, &file_name, reporter.as_ref())?;
     
     // We are doing a file transfer, so it may take some time for the final response.
     // Just increase the timeout here
     device.set_timeout(Some(Duration::new(15, 0)));
 
-    
-
-    // We will also setup a spinner so the user knows that the application has not frozen.
-    //let sp = Spinner::with_timer(Spinners::Dots, "Closing file handle".to_string());
-    let sp = ProgressBar::new_spinner();
-    sp.set_message("Closing file handle");
-    sp.enable_steady_tick(std::time::Duration::from_millis(100));
-
     // Close file
-    fh.close(vexv5_serial::device::VexFiletransferFinished::ShowRunScreen)?;
-    
-    // And stop the spinner
-    sp.finish_and_clear();
-    println!("\x1b[F\x1b[32m✔\x1b[0m Finished closing file handle in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32());
-
+    reporter.report(&TransferEvent::Closing { file: &file_name });
+    fh.close(options.finished)?;
 
     // Reset the timeout to default
     device.set_timeout(None);
 
-
     // Log that the file has been successfully uploaded
-    println!("\x1b[F\x1b[32m✔\x1b[0m {} {} {}", 
-        style("Successfully uploaded file").bold(),
-        style(file_name).cyan().bright(),
-        style(format!("in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32())).bold()
-    );
+    reporter.report(&TransferEvent::Finished {
+        file: &file_name,
+        direction: Direction::Upload,
+        size,
+        crc,
+        seconds: std::time::SystemTime::now().duration_since(time)?.as_secs_f32(),
+    });
 
     Ok(())
 }
@@ -90,33 +175,29 @@ pub fn upload_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String
 
 /// Reads a file from the V5 brain.
 pub fn download_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: String) -> Result<Vec<u8>> {
+    download_file_with(device, file_name, DownloadOptions::default())
+}
+
+/// Reads a file from the V5 brain, choosing where its progress is reported and whether to ask before large transfers.
+pub fn download_file_with<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, options: DownloadOptions) -> Result<Vec<u8>> {
+    radio::with_download_channel(device, |device| download_file_inner(device, file_name, options))
+}
+
+fn download_file_inner<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, options: DownloadOptions) -> Result<Vec<u8>> {
 
     // Begin timer
     let time = std::time::SystemTime::now();
 
-    println!("{} {}", style("Downloading File").bright(), style(file_name.clone()).cyan().bright());
+    let reporter = options.reporter.unwrap_or_else(progress::default_reporter);
 
     // Retrieve the file metadata
     let metadata = device.file_metadata_from_name(file_name.clone(), None, None)?;
 
-    // If the file size is too large (lets say 16 KiB) and the device is a controller
-    // then prompt before downloading.
-    if let V5DeviceVersion{system_version: _, product_type: VexProduct::V5Controller(_) } = device.get_device_version()? {
-        if metadata.size > 64000 {
-            let prompt = format!(
-                "You are downloading a large ({}) file wirelessly. This is projected to take {} to complete. Are you sure you want to continue?",
-                HumanBytes(metadata.size as u64),
-                HumanDuration(Duration::from_secs(metadata.size as u64 / 1024)) // The average download speed at close range is ~1 KiB/s
-            );
-            if Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default()).with_prompt(prompt).interact()? {
-                // Continue
-            } else {
-                // Abort
-                return Err(anyhow::anyhow!("Aborted download due to user request"));
-            }
-        }
+    if options.interactive && !confirm_wireless(device, metadata.size as u64, "downloading")? {
+        return Err(anyhow::anyhow!("Aborted download due to user request"));
     }
-    
+
+    reporter.report(&TransferEvent::Started { file: &file_name, direction: Direction::Download, size: metadata.size.into() });
 
     // Write to the slot_1.ini file on the brain
     let mut fh = device.open(file_name.clone(), Some(vexv5_serial::device::VexInitialFileMetadata {
@@ -135,37 +216,59 @@ pub fn download_file<T: Read + Write>(device: &mut VexDevice<T>, file_name: Stri
     
 
     // Read data
-    let data = util::read_file_progress(&mut fh)?;
+    let data = util::read_file_progress(&mut fh, &file_name, reporter.as_ref())?;
     
     // We are doing a file transfer, so it may take some time for the final response.
     // Just increase the timeout here
     device.set_timeout(Some(Duration::new(15, 0)));
 
-
-    // We will also setup a spinner so the user knows that the application has not frozen.
-    //let sp = Spinner::with_timer(Spinners::Dots, "Closing file handle".to_string());
-    let sp = ProgressBar::new_spinner();
-    sp.set_message("Closing file handle");
-    sp.enable_steady_tick(std::time::Duration::from_millis(100));
-
     // Close file
+    reporter.report(&TransferEvent::Closing { file: &file_name });
     fh.close(vexv5_serial::device::VexFiletransferFinished::ShowRunScreen)?;
-    
-    // And stop the spinner
-    sp.finish_and_clear();
-    println!("\x1b[F\x1b[32m✔\x1b[0m Finished closing file handle in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32());
-
 
     // Reset the timeout to default
     device.set_timeout(None);
 
+    // Log that the file has been successfully downloaded
+    reporter.report(&TransferEvent::Finished {
+        file: &file_name,
+        direction: Direction::Download,
+        size: data.len() as u64,
+        crc: crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data),
+        seconds: std::time::SystemTime::now().duration_since(time)?.as_secs_f32(),
+    });
 
-    // Log that the file has been successfully uploaded
-    println!("\x1b[F\x1b[32m✔\x1b[0m {} {} {}", 
-        style("Successfully downloaded file").bold(),
-        style(file_name).cyan().bright(),
-        style(format!("in {:.3} seconds", std::time::SystemTime::now().duration_since(time)?.as_secs_f32())).bold()
-    );
+    Ok(data)
+}
+
+/// Reads a file from the V5 brain without looking up its metadata first.
+/// Used for special files, like the screen buffer, that have no directory entry.
+pub fn download_raw<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, target: VexFileTarget, vid: VexVID, length: u32, addr: u32) -> Result<Vec<u8>> {
+    radio::with_download_channel(device, |device| download_raw_inner(device, file_name, target, vid, length, addr))
+}
+
+fn download_raw_inner<T: Read + Write>(device: &mut VexDevice<T>, file_name: String, target: VexFileTarget, vid: VexVID, length: u32, addr: u32) -> Result<Vec<u8>> {
+
+    let mut fh = device.open(file_name.clone(), Some(vexv5_serial::device::VexInitialFileMetadata {
+        function: vexv5_serial::device::VexFileMode::Download(target, true),
+        vid,
+        options: 0,
+        length,
+        addr,
+        crc: 0,
+        r#type: *b"bin\0",
+        timestamp: 0,
+        version: 0,
+        linked_name: None,
+    }))?;
+
+    // Only the progress is reported, since these transfers are part of something bigger
+    let data = util::read_file_progress(&mut fh, &file_name, progress::default_reporter().as_ref())?;
+
+    // Close file without disturbing whatever is on screen
+    device.set_timeout(Some(Duration::new(15, 0)));
+    fh.close(VexFiletransferFinished::DoNothing)?;
+    device.set_timeout(None);
 
     Ok(data)
-}
\ No newline at end of file
+}
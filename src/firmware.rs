@@ -0,0 +1,320 @@
+use std::io::{Cursor, Read, Write};
+
+use anyhow::Result;
+use console::style;
+use dialoguer::Confirm;
+use indicatif::HumanBytes;
+use serde::{Serialize, Deserialize};
+use vexv5_serial::device::{VexDevice, VexProduct, VexVID, VexFiletransferFinished};
+
+use crate::{config::V5Metadata, error::Error, files::{self, UploadOptions}, output::{self, Message}, radio, system::{self, FirmwareVersion}, util};
+
+
+/// VEXos is distributed as a zip archive, which starts with a local file header
+const PACKAGE_MAGIC: &[u8] = b"PK\x03\x04";
+/// The battery percentage the brain needs before we start an update
+const MIN_UPDATE_BATTERY: u8 = 50;
+/// The name the package is uploaded under
+const PACKAGE_FILE_NAME: &str = "vexos.vexos";
+
+
+/// What to do when the connected firmware is older than the project requires
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirmwarePolicy {
+    /// Print a warning and upload anyway
+    Warn,
+    /// Refuse to upload
+    #[default]
+    Refuse,
+}
+
+/// Converts the version reported by `get_device_version` into a firmware version
+fn device_version(version: (u8, u8, u8, u8, u8)) -> FirmwareVersion {
+    // The fourth byte is the hotfix number, which we don't compare on
+    FirmwareVersion { major: version.0, minor: version.1, build: version.2, beta: version.4 }
+}
+
+/// Parses a version from the project config
+fn config_version(key: &str, version: &str) -> Result<FirmwareVersion> {
+    version.parse()
+        .map_err(|e| Error::InvalidConfig(format!("{} is not a valid version: {}", key, e)).into())
+}
+
+/// Checks the brain's and controller's firmware against the minimum versions in the project config.
+/// Depending on the project's policy this either warns or fails when one of them is too old.
+pub fn check_firmware<T: Read + Write>(device: &mut VexDevice<T>, config: &V5Metadata) -> Result<()> {
+    // Nothing to do if the project doesn't care
+    if config.min_vexos.is_none() && config.min_controller_firmware.is_none() {
+        return Ok(());
+    }
+
+    let version = device.get_device_version()?;
+
+    // When connected through a controller, the device version is the controller's own
+    // and the brain has to be asked separately.
+    let (brain, controller) = match version.product_type {
+        VexProduct::V5Controller(_) => (
+            system::get_system_status(device)?.system_version,
+            Some(device_version(version.system_version)),
+        ),
+        _ => (device_version(version.system_version), None),
+    };
+
+    // Collect everything that is out of date
+    let mut problems = Vec::<String>::new();
+    if let Some(min) = &config.min_vexos {
+        let min = config_version("min-vexos", min)?;
+        if brain < min {
+            problems.push(format!("The brain is running VEXos {} but this project requires at least {}.", brain, min));
+        }
+    }
+    if let (Some(min), Some(controller)) = (&config.min_controller_firmware, controller) {
+        let min = config_version("min-controller-firmware", min)?;
+        if controller < min {
+            problems.push(format!("The controller is running firmware {} but this project requires at least {}.", controller, min));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    match config.firmware_policy.unwrap_or_default() {
+        FirmwarePolicy::Warn => {
+            for problem in &problems {
+                output::warn(problem);
+            }
+            Ok(())
+        },
+        FirmwarePolicy::Refuse => Err(Error::FirmwareTooOld { problems }.into()),
+    }
+}
+
+
+/// Reads a version from a name like `VEXOS_1_1_2` or `VEXOS_1_1_2_b3`, ignoring any extension
+fn version_from_name(name: &str) -> Option<FirmwareVersion> {
+    let stem = name.split('.').next()?;
+    let version = stem.strip_prefix("VEXOS_").or_else(|| stem.strip_prefix("vexos_"))?;
+
+    // The last part is the beta number, if there is one
+    let (version, beta) = match version.rsplit_once("_b") {
+        Some((version, beta)) => (version, Some(beta)),
+        None => (version, None),
+    };
+    let version = match beta {
+        Some(beta) => format!("{}-b{}", version.replace('_', "."), beta),
+        None => version.replace('_', "."),
+    };
+
+    version.parse().ok()
+}
+
+/// Packs a version into the 32 bit form used in file metadata
+fn pack_version(version: FirmwareVersion) -> u32 {
+    u32::from_be_bytes([version.major, version.minor, version.build, version.beta])
+}
+
+/// A VEXos package that has been checked before anything is sent to the brain
+#[derive(Clone, Debug)]
+pub struct FirmwareImage {
+    /// The VEXos version the package contains
+    pub version: FirmwareVersion,
+    /// How many files the package holds
+    pub entries: usize,
+    /// The CRC32 of the whole package, as the brain computes it
+    pub crc: u32,
+    /// The package itself
+    pub data: Vec<u8>,
+}
+
+/// Checks that a file is an intact VEXos package and reads the version it contains.
+/// Every file in the package is read back against its CRC, and the version has to be
+/// named by the package's own contents, so a renamed program or a damaged download is refused.
+pub fn validate_image(data: Vec<u8>) -> Result<FirmwareImage> {
+    if !data.starts_with(PACKAGE_MAGIC) {
+        return Err(anyhow::anyhow!("This is not a VEXos package. VEXos is distributed as a .vexos archive, and a program or raw .bin can't be installed as firmware."));
+    }
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(&data))
+        .map_err(|e| anyhow::anyhow!("The VEXos package is damaged: {}", e))?;
+    if archive.is_empty() {
+        return Err(anyhow::anyhow!("The VEXos package is empty"));
+    }
+
+    let mut versions = Vec::<FirmwareVersion>::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+
+        // Reading an entry to the end checks it against its CRC
+        std::io::copy(&mut entry, &mut std::io::sink())
+            .map_err(|e| anyhow::anyhow!("{} in the VEXos package is damaged: {}", name, e))?;
+
+        // The package's folder and files are named after the version they hold
+        for version in name.split('/').filter_map(version_from_name) {
+            if !versions.contains(&version) {
+                versions.push(version);
+            }
+        }
+    }
+
+    let version = match versions.as_slice() {
+        [version] => *version,
+        [] => return Err(anyhow::anyhow!("The VEXos package does not say which version it contains")),
+        _ => return Err(anyhow::anyhow!("The VEXos package contains more than one version: {}",
+            versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", "))),
+    };
+
+    Ok(FirmwareImage {
+        version,
+        entries: archive.len(),
+        crc: crc::Crc::<u32>::new(&vexv5_serial::protocol::VEX_CRC32).checksum(&data),
+        data,
+    })
+}
+
+/// Options for updating the brain's firmware
+#[derive(clap::Args, Debug, Clone)]
+pub struct UpdateOptions {
+    /// The VEXos package to install, such as VEXOS_1_1_2.vexos
+    pub image: String,
+    /// Allow installing an older version than the brain is running
+    #[clap(long)]
+    pub allow_downgrade: bool,
+    /// Don't ask for confirmation before updating
+    #[clap(long)]
+    pub yes: bool,
+}
+
+/// Validates a local VEXos package, uploads it to the brain as a system file and starts the update
+pub fn update_firmware<T: Read + Write>(device: &mut VexDevice<T>, options: &UpdateOptions) -> Result<()> {
+    // Validate the package before touching the brain
+    let image = validate_image(std::fs::read(&options.image)?)
+        .map_err(|e| e.context(format!("Could not install {}", options.image)))?;
+
+    // Updating over the radio is far too slow and fragile
+    if radio::through_controller(device)? {
+        return Err(anyhow::anyhow!("Firmware can only be updated with the brain connected over USB"));
+    }
+
+    // Make sure this is actually an update
+    let current = system::get_system_status(device)?.system_version;
+    if image.version == current {
+        return Err(anyhow::anyhow!("The brain is already running VEXos {}", current));
+    }
+    if image.version < current && !options.allow_downgrade {
+        return Err(anyhow::anyhow!("The brain is running VEXos {}, which is newer than {}. Pass --allow-downgrade to install it anyway.", current, image.version));
+    }
+
+    // Losing power halfway through an update is the one way to really break a brain
+    let flags = system::get_system_flags(device)?;
+    if flags.battery < MIN_UPDATE_BATTERY {
+        return Err(anyhow::anyhow!("The brain's battery is at {}%. Charge it to at least {}% before updating.", flags.battery, MIN_UPDATE_BATTERY));
+    }
+
+    if !output::is_json() {
+        println!("{} {} ({} in {} files, CRC {:08X})",
+            style("VEXos package").bright(),
+            style(&options.image).cyan().bright(),
+            HumanBytes(image.data.len() as u64),
+            image.entries,
+            image.crc,
+        );
+    }
+
+    // Never start an update nobody agreed to
+    if !options.yes {
+        if output::is_json() || !console::user_attended() {
+            return Err(anyhow::anyhow!("Pass --yes to update the firmware without a prompt"));
+        }
+
+        let prompt = format!(
+            "This will update the brain from VEXos {} to {}. Do not disconnect or power off the brain until it restarts. Continue?",
+            current, image.version
+        );
+        if !Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default()).with_prompt(prompt).interact()? {
+            return Err(anyhow::anyhow!("Aborted firmware update due to user request"));
+        }
+    }
+
+    // Nothing should be running while the system is replaced
+    device.execute_program_file("".to_string(), None, Some(util::EXECUTE_STOP))?;
+
+    // Upload the package as a system file
+    let version = image.version;
+    files::upload_file_with(device, PACKAGE_FILE_NAME.to_string(), image.data, UploadOptions {
+        finished: VexFiletransferFinished::DoNothing,
+        vid: VexVID::SYSTEM,
+        addr: 0,
+        version: pack_version(version),
+        r#type: *b"vex\0",
+        ..Default::default()
+    })?;
+
+    // And ask VEXos to install it
+    device.execute_program_file(PACKAGE_FILE_NAME.to_string(), Some(VexVID::SYSTEM), None)?;
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "firmware", data: serde_json::json!({
+            "from": current.to_string(),
+            "to": version.to_string(),
+            "crc": image.crc,
+        }) });
+    } else {
+        println!("{} {}", output::pass_mark(), style(format!("Started the update to VEXos {}. The brain will restart once it is installed.", version)).bold());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a package holding the given files, stored without compression
+    fn package(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_versions_from_names() {
+        assert_eq!(version_from_name("VEXOS_1_1_2"), "1.1.2".parse().ok());
+        assert_eq!(version_from_name("VEXOS_1_1_2_b3.vexos"), "1.1.2-b3".parse().ok());
+        assert_eq!(version_from_name("BOOT.bin"), None);
+        assert_eq!(version_from_name("VEXOS_1_x_2"), None);
+    }
+
+    #[test]
+    fn accepts_intact_package() {
+        let image = validate_image(package(&[("VEXOS_1_1_2/BOOT.bin", b"boot"), ("VEXOS_1_1_2/assets.bin", b"assets")])).unwrap();
+        assert_eq!(image.version, "1.1.2".parse().unwrap());
+        assert_eq!(image.entries, 2);
+    }
+
+    #[test]
+    fn refuses_programs() {
+        let program = [0x00, 0x00, 0xa0, 0xe1].repeat(1024);
+        assert!(validate_image(program).is_err());
+    }
+
+    #[test]
+    fn refuses_damaged_package() {
+        let mut data = package(&[("VEXOS_1_1_2/BOOT.bin", b"boot image")]);
+        let at = data.windows(10).position(|w| w == b"boot image").unwrap();
+        data[at] ^= 0xff;
+        assert!(validate_image(data).is_err());
+    }
+
+    #[test]
+    fn refuses_package_without_one_version() {
+        assert!(validate_image(package(&[("BOOT.bin", b"boot")])).is_err());
+        assert!(validate_image(package(&[("VEXOS_1_1_2/BOOT.bin", b"boot"), ("VEXOS_1_1_3/BOOT.bin", b"boot")])).is_err());
+    }
+}
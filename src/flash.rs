@@ -0,0 +1,153 @@
+use std::{sync::Arc, thread};
+
+use anyhow::Result;
+use console::style;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use vexv5_serial::{device::VexFiletransferFinished, ports::VexSerialClass};
+
+use crate::{config::V5Metadata, files::{self, AfterUpload, UploadOptions}, firmware, output::{self, Message}, progress::Reporter, util::{self, DevicePair}};
+
+
+/// Returns true if a device pair is a brain connected over USB, rather than a controller
+fn is_brain(pair: &DevicePair) -> bool {
+    match pair {
+        DevicePair::Double(_, _) => true,
+        DevicePair::Single(d) => matches!(d.class, VexSerialClass::System),
+    }
+}
+
+/// The program to upload to every brain, and how
+#[derive(Clone, Debug)]
+pub struct FlashJob {
+    /// The slot's ini file
+    pub ini: Vec<u8>,
+    /// The program's .bin
+    pub data: Vec<u8>,
+    /// The program slot, counting from zero
+    pub slot: u8,
+    /// What each brain does once the program is uploaded
+    pub after: AfterUpload,
+    /// The project config, for the firmware check
+    pub config: V5Metadata,
+    /// Whether to check each brain's firmware before uploading
+    pub check_firmware: bool,
+}
+
+/// Uploads a program to a single brain, reporting progress into `bar`.
+/// Once connected, the brain's name is added to `label`.
+fn flash_one(pair: DevicePair, job: FlashJob, bar: &ProgressBar, label: &mut String) -> Result<()> {
+    let FlashJob { ini, data, slot, after, config, check_firmware } = job;
+
+    let mut device = util::connect(pair)?;
+
+    // Name the brain using the connection we already have
+    *label = util::with_identity(util::read_identity(&mut device), label.clone());
+    bar.set_prefix(label.clone());
+
+    // Make sure the brain can actually run the program
+    if check_firmware {
+        firmware::check_firmware(&mut device, &config)?;
+    }
+
+    // Move this brain's bar along as the files are written
+    let reporter: Arc<dyn Reporter> = {
+        let bar = bar.clone();
+        Arc::new(move |done: u64, total: u64| {
+            bar.set_length(total);
+            bar.set_position(done);
+        })
+    };
+
+    bar.set_message(format!("Uploading slot_{}.ini", slot+1));
+    files::upload_file_with(&mut device, format!("slot_{}.ini", slot+1), ini, UploadOptions {
+        finished: VexFiletransferFinished::DoNothing,
+        reporter: Some(reporter.clone()),
+        ..Default::default()
+    })?;
+
+    bar.set_message(format!("Uploading slot_{}.bin", slot+1));
+    files::upload_file_with(&mut device, format!("slot_{}.bin", slot+1), data, UploadOptions {
+        finished: after.into(),
+        reporter: Some(reporter),
+        ..Default::default()
+    })?;
+
+    Ok(())
+}
+
+/// Uploads a program to every brain connected over USB at the same time, with a progress bar for each.
+/// Returns true if every brain was flashed.
+pub fn flash_all(job: &FlashJob) -> Result<bool> {
+    let (brains, others): (Vec<DevicePair>, Vec<DevicePair>) = util::discover_pairs()?.into_iter().partition(is_brain);
+    let json = output::is_json();
+
+    // Controllers share one radio link, so flashing through them in parallel would just fight over it
+    if !json {
+        for pair in &others {
+            println!("{} {}", style("Skipping").yellow().bright(), util::describe_ports(pair));
+        }
+    }
+
+    if brains.is_empty() {
+        return Err(anyhow::anyhow!("No brains connected over USB"));
+    }
+
+    // With JSON messages, each brain's result is reported once it is done instead
+    let multi = if json {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        println!("{} slot {} on {} brains", style("Flashing").bright(), job.slot+1, brains.len());
+        MultiProgress::new()
+    };
+    let bar_style = ProgressStyle::default_bar()
+        .template("{prefix:40} {bar:30.cyan/blue} {percent:>3}% {msg}")?
+        .progress_chars("##-");
+
+    // Start a thread for each brain, each with its own connection
+    let mut handles = Vec::new();
+    for pair in brains {
+        // Brains are named once their thread connects, so nothing is opened twice
+        let mut label = util::describe_ports(&pair);
+
+        let bar = multi.add(ProgressBar::new(0));
+        bar.set_style(bar_style.clone());
+        bar.set_prefix(label.clone());
+
+        let job = job.clone();
+        let ports = label.clone();
+        let handle = thread::spawn(move || {
+            let result = flash_one(pair, job, &bar, &mut label);
+            match &result {
+                Ok(()) => bar.finish_with_message("done"),
+                Err(_) => bar.abandon_with_message("failed"),
+            }
+            (label, result)
+        });
+
+        handles.push((ports, handle));
+    }
+
+    // Wait for every brain before summarizing
+    let results: Vec<(String, Result<()>)> = handles.into_iter().map(|(ports, handle)| {
+        handle.join().unwrap_or_else(|_| (ports, Err(anyhow::anyhow!("Upload thread panicked"))))
+    }).collect();
+
+    if !json {
+        println!();
+    }
+    let mut all_ok = true;
+    for (label, result) in results {
+        all_ok &= result.is_ok();
+        if json {
+            output::emit(&Message::Flashed { device: &label, error: result.err().map(|e| format!("{:#}", e)) });
+            continue;
+        }
+
+        match result {
+            Ok(()) => println!("{} {}", output::pass_mark(), style(label).bold()),
+            Err(e) => println!("{} {}: {}", output::fail_mark(), style(label).bold(), style(e).red()),
+        }
+    }
+
+    Ok(all_ok)
+}
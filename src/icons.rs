@@ -0,0 +1,59 @@
+use anyhow::Result;
+use console::style;
+
+use crate::output::{self, Message};
+
+
+/// The program icons built into VEXos, by name and number.
+/// These are the same names PROS uses.
+pub const ICONS: [(&str, u16); 10] = [
+    ("x", 1),
+    ("question", 2),
+    ("pizza", 3),
+    ("clawbot", 10),
+    ("robot", 11),
+    ("power", 12),
+    ("planet", 13),
+    ("alien", 27),
+    ("ufo", 29),
+    ("pros", 902),
+];
+
+/// The icon used when a project doesn't choose one: the Vex X logo
+pub const DEFAULT_ICON: &str = "x";
+
+/// Returns the file name VEXos uses for an icon number
+fn icon_file(number: u16) -> String {
+    format!("USER{:03}x.bmp", number)
+}
+
+/// Resolves an icon given by name or number into its file name on the brain
+pub fn resolve(icon: &str) -> Result<String> {
+    let icon = icon.trim();
+
+    let found = match icon.parse::<u16>() {
+        Ok(number) => ICONS.iter().find(|(_, n)| *n == number),
+        Err(_) => ICONS.iter().find(|(name, _)| name.eq_ignore_ascii_case(icon)),
+    };
+
+    match found {
+        Some((_, number)) => Ok(icon_file(*number)),
+        None => Err(anyhow::anyhow!("Unknown icon {:?}. Run `cargo v5 icons` to see the available icons.", icon)),
+    }
+}
+
+/// Prints the catalog of available icons
+pub fn print_icons() {
+    if output::is_json() {
+        let icons: Vec<_> = ICONS.iter()
+            .map(|(name, number)| serde_json::json!({ "name": name, "number": number, "file": icon_file(*number) }))
+            .collect();
+        output::emit(&Message::Report { command: "icons", data: serde_json::Value::Array(icons) });
+        return;
+    }
+
+    println!("{}", style(format!("{:<10}{:<8}{}", "Name", "Number", "File")).bold());
+    for (name, number) in ICONS {
+        println!("{:<10}{:<8}{}", style(name).cyan(), number, icon_file(number));
+    }
+}
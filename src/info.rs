@@ -0,0 +1,85 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use console::style;
+use indicatif::HumanBytes;
+use vexv5_serial::device::{VexDevice, VexVID};
+
+use crate::{output::{self, Message}, system};
+
+
+/// Prints a labelled line of the report
+fn field(label: &str, value: impl std::fmt::Display) {
+    println!("  {:<20} {}", style(label).bold(), value);
+}
+
+/// Prints a summary of the brain's firmware, batteries, radio and storage
+pub fn print_info<T: Read + Write>(device: &mut VexDevice<T>) -> Result<()> {
+    // Gather everything first so a failed query doesn't leave a half printed report
+    let version = device.get_device_version()?;
+    let status = system::get_system_status(device)?;
+    let flags = system::get_system_flags(device)?;
+    let radio = system::get_radio_status(device)?;
+    let files = system::list_files(device, VexVID::USER as u8)?;
+    let used: u64 = files.iter().map(|f| f.size as u64).sum();
+
+    if output::is_json() {
+        output::emit(&Message::Report {
+            command: "info",
+            data: serde_json::json!({
+                "connected_through": format!("{:?}", version.product_type),
+                "vexos": status.system_version.to_string(),
+                "cpu0": status.cpu0_version.to_string(),
+                "cpu1": status.cpu1_version.to_string(),
+                "nxp": status.nxp_version,
+                "system_id": status.system_id,
+                "battery": flags.battery,
+                "controller_battery": flags.controller_battery,
+                "partner_battery": flags.partner_battery,
+                "radio_linked": radio.linked(),
+                "radio_channel": radio.channel,
+                "radio_quality": radio.quality,
+                "radio_strength": radio.strength,
+                "user_files": files.len(),
+                "flash_used": used,
+                // The brain doesn't report its capacity, so free space can't be worked out
+                "flash_free": serde_json::Value::Null,
+                "running_slot": flags.current_program,
+            }),
+        });
+        return Ok(());
+    }
+
+    println!("{}", style("System").cyan().bright());
+    field("Connected through", format!("{:?}", version.product_type));
+    field("VEXos", status.system_version);
+    field("CPU0", status.cpu0_version);
+    field("CPU1", status.cpu1_version);
+    field("NXP", status.nxp_version);
+    field("System ID", format!("{:08X}", status.system_id));
+
+    println!("{}", style("Battery").cyan().bright());
+    field("Brain", format!("{}%", flags.battery));
+    field("Controller", format!("{}%", flags.controller_battery));
+    field("Partner controller", format!("{}%", flags.partner_battery));
+
+    println!("{}", style("Radio").cyan().bright());
+    field("Link", if radio.linked() { style("connected").green() } else { style("not connected").red() });
+    field("Channel", radio.channel);
+    field("Quality", radio.quality);
+    field("Strength", radio.strength);
+
+    // The brain does not report its capacity, so the best we can do is show what is in use
+    println!("{}", style("Storage").cyan().bright());
+    field("User files", files.len());
+    field("Used by user files", HumanBytes(used));
+    field("Free", style("unknown, the brain does not report its capacity").black().bright());
+
+    println!("{}", style("Program").cyan().bright());
+    match flags.current_program {
+        Some(slot) => field("Running", format!("slot {}", slot)),
+        None => field("Running", style("nothing").black().bright()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! The device operations behind the `cargo v5` command, for tools that want to talk to a V5 brain themselves.
+//!
+//! Devices are found with [`util::discover_pairs`] and connected to with [`util::connect`].
+//! Files are transferred with [`files::upload_file_with`] and [`files::download_file_with`],
+//! which report their progress to a [`progress::Reporter`].
+//! Nothing in the library prompts on stdin unless asked to with `interactive`.
+//! Program output is read with [`terminal::stream`].
+
+pub mod util;
+pub mod files;
+pub mod system;
+pub mod terminal;
+pub mod runner;
+pub mod crash;
+pub mod config;
+pub mod info;
+pub mod ports;
+pub mod firmware;
+pub mod screenshot;
+pub mod icons;
+pub mod radio;
+pub mod backup;
+pub mod flash;
+pub mod progress;
+pub mod output;
+pub mod error;
+pub mod doctor;
+pub mod target;
+pub mod scaffold;
+pub mod size;
+pub mod build;
@@ -1,31 +1,33 @@
-use std::io::{Read, Write};
-
 use anyhow::Result;
 use ascii::AsAsciiStr;
 use clap::{Parser, Subcommand};
 use chrono::prelude::{DateTime, Utc};
-use serde::{Serialize, Deserialize};
-use vexrs_serial::protocol::VexrsSerial;
-use vexv5_serial::device::{VexDevice, V5ControllerChannel};
-
-
-
+use dialoguer::{Select, theme::ColorfulTheme};
+use vexv5_serial::device::VexFiletransferFinished;
 
-mod util;
-mod files;
+use cargo_v5::{backup, build, config, doctor, files, firmware, flash, icons, info, ports, radio, runner, scaffold, screenshot, size, system, target, terminal, util};
+use cargo_v5::files::{AfterUpload, DownloadOptions, UploadOptions};
+use cargo_v5::error::{self, Error};
+use cargo_v5::output::{self, Message, MessageFormat};
 
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
     #[clap(subcommand)]
-    command: Commands
+    command: Commands,
+    /// How to write messages: colored text, or one JSON object per line
+    #[clap(long, value_enum, global = true, default_value_t = MessageFormat::Human)]
+    message_format: MessageFormat,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
     /// Opens a terminal connection to the v5 brain
-    Terminal {},
+    Terminal {
+        #[clap(flatten)]
+        options: terminal::TerminalOptions,
+    },
     /// Downloads a file from the brain
     Download {
         /// The file to download
@@ -34,48 +36,143 @@ enum Commands {
     /// Uploads a file to the brain
     Upload {
         /// The file to upload
-        file: String
+        file: String,
+        /// What the brain should do once the file is uploaded
+        #[clap(long, value_enum, default_value_t = AfterUpload::ShowRunScreen)]
+        after: AfterUpload,
+    },
+    /// Runs a program that has already been uploaded to the brain
+    Run {
+        /// The slot to run (0-7). Defaults to the slot in the slot file, or 0 if there is none.
+        #[clap(long, value_parser = clap::value_parser!(u8).range(0..=util::MAX_SLOT as i64))]
+        slot: Option<u8>,
+        /// Open the terminal once the program has started
+        #[clap(long)]
+        terminal: bool,
+        #[clap(flatten)]
+        options: terminal::TerminalOptions,
+    },
+    /// Stops the program running on the brain
+    Stop {},
+    /// Shows firmware versions, batteries, radio and storage of the brain
+    Info {},
+    /// Lists the devices plugged into each smart port
+    Ports {
+        /// Compare the devices against the layout in the project config
+        #[clap(long)]
+        diff: bool,
+    },
+    /// Captures the brain's screen to a PNG, or an animated GIF with --gif
+    Screenshot {
+        /// Where to write the image. Defaults to screenshot.png or screenshot.gif.
+        output: Option<String>,
+        /// Capture repeatedly into an animated GIF
+        #[clap(long)]
+        gif: bool,
+        /// The number of frames to capture for a GIF
+        #[clap(long, default_value_t = 10, requires = "gif")]
+        frames: u32,
+        /// The time to wait between frames, in milliseconds
+        #[clap(long, default_value_t = 500, requires = "gif")]
+        interval: u64,
+    },
+    /// Downloads every user file on the brain into a backup directory
+    Backup {
+        /// The directory to write the backup to. Defaults to one named after the current time.
+        output: Option<String>,
+    },
+    /// Uploads the files in a backup directory back to the brain
+    Restore {
+        /// The backup directory to restore
+        backup: String,
+    },
+    /// Updates the brain's firmware from a local VEXos package
+    Firmware {
+        #[clap(flatten)]
+        options: firmware::UpdateOptions,
+    },
+    /// Lists the program icons built into the brain
+    Icons {},
+    /// Creates a new V5 project
+    New {
+        /// The directory to create the project in
+        #[clap(required_unless_present = "list")]
+        path: Option<String>,
+        #[clap(flatten)]
+        options: scaffold::ScaffoldOptions,
+    },
+    /// Sets up a V5 project in an existing directory, without overwriting any files
+    Init {
+        /// The directory to set up
+        #[clap(default_value = ".")]
+        path: String,
+        #[clap(flatten)]
+        options: scaffold::ScaffoldOptions,
+    },
+    /// Builds the project for the brain and converts it to a .bin
+    Build {
+        #[clap(flatten)]
+        options: build::BuildOptions,
+    },
+    /// Shows whether the project's target spec and linker script are up to date
+    Target {
+        #[clap(flatten)]
+        options: target::TargetOptions,
+    },
+    /// Checks that this machine can build programs and talk to VEX devices
+    Doctor {
+        #[clap(flatten)]
+        options: doctor::DoctorOptions,
+    },
+    /// Shows the radio's channel and link, or switches the controller's channel
+    Radio {
+        /// Switch the controller to this channel
+        #[clap(long, value_enum)]
+        channel: Option<system::RadioChannel>,
+    },
+    /// Shows or sets the brain's name
+    Name {
+        /// The new name. Shows the current name if omitted.
+        name: Option<String>,
+    },
+    /// Shows or sets the brain's team number
+    Team {
+        /// The new team number. Shows the current team number if omitted.
+        number: Option<String>,
+    },
+    /// Runs a test binary on the brain and reports the results like libtest.
+    /// Use this as the runner for cargo test.
+    Test {
+        #[clap(flatten)]
+        options: runner::TestOptions,
     },
     /// Should be used by cargo only. Generates files, uploads a program and runs it.
     CargoHook {
         /// The program file to upload
         file: String,
+        /// What the brain should do once the program is uploaded.
+        /// Defaults to the project's after-upload setting, or run.
+        #[clap(long, value_enum)]
+        after: Option<AfterUpload>,
+        /// Do not open the terminal after uploading
+        #[clap(long)]
+        no_terminal: bool,
+        /// Upload even if the firmware is older than the project requires
+        #[clap(long)]
+        ignore_firmware: bool,
+        /// The program icon, by name or number. Defaults to the project's icon setting.
+        #[clap(long)]
+        icon: Option<String>,
+        /// Upload to every brain connected over USB at once. Does not open the terminal.
+        #[clap(long)]
+        all: bool,
+        #[clap(flatten)]
+        options: terminal::TerminalOptions,
     }
 }
 
 
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Package {
-    name: String,
-    version: String,
-    description: Option<String>,
-}
-#[derive(Serialize, Deserialize, Debug)]
-struct CargoToml {
-    package: Package,
-}
-
-
-fn terminal<T: Read+Write>(device: &mut VexDevice<T>) -> Result<()> {
-    // We want to use a download channel
-    device.with_channel(V5ControllerChannel::UPLOAD, |device| {
-        
-        let mut serial = VexrsSerial::new(device);
-        loop {
-            
-            let data_type = serial.read_data()?;
-            
-            // Print the data if we need to print
-            if let vexrs_serial::data::DataType::Print(d) = data_type {
-                print!("{}", std::str::from_utf8(&d)?);
-                std::io::stdout().flush()?;
-            }
-        }
-    })?;
-    Ok(())
-}
-
 fn main() -> Result<()>{
     
     let args: Vec<String> = std::env::args().collect();
@@ -92,74 +189,270 @@ fn main() -> Result<()>{
     // Parse the args
     let args = Args::parse_from(args);
 
-    // Find and prepare the raw device to use
-    let device = util::find_devices()?;
-    let (system, user) = util::prepare_device(device)?;
-    
-    // Create the wrapper
-    let mut device = VexDevice::new(system, user)?;
+    output::set_message_format(args.message_format);
+
+    // Begin timer
+    let start = std::time::Instant::now();
+
+    let status = match run(args.command) {
+        Ok(status) => status,
+        Err(e) => {
+            // Explain the errors we know about, and give each kind its own exit status
+            let known = Error::classify(&e);
+            if output::is_json() {
+                output::emit(&Message::Error {
+                    message: e.to_string(),
+                    causes: e.chain().skip(1).map(|c| c.to_string()).collect(),
+                    kind: known.as_ref().map(Error::kind),
+                    hint: known.as_ref().and_then(Error::hint),
+                });
+            } else {
+                error::print_error(&e, known.as_ref());
+            }
+            known.as_ref().map_or(1, Error::exit_status)
+        },
+    };
+
+    output::emit(&Message::Finished { status, seconds: start.elapsed().as_secs_f32() });
+
+    // Pass the program's exit status on to whoever ran us
+    if status != 0 {
+        std::process::exit(status);
+    }
+
+    Ok(())
+}
+
+/// Finds which vex device to use, asking the user if there is more than one
+fn select_device() -> Result<util::DevicePair> {
+    let pairs = util::discover_pairs()?;
+
+    // If there is only one device, then use it.
+    // If not, then ask which one to use
+    if pairs.len() == 1 {
+        return Ok(pairs[0].clone());
+    }
+
+    // Generate a list of selections (just differently formatted devices)
+    let pselect: Vec<String> = pairs.iter().map(util::describe_pair).collect();
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .items(&pselect)
+        .default(0)
+        .with_prompt("Multiple Vex devices found. Please select which one to use:")
+        .interact()?;
+
+    Ok(pairs[selection].clone())
+}
+
+/// Runs a command, returning the exit status to report once it is done
+fn run(command: Commands) -> Result<i32> {
+    // Not every command talks to the brain, so only connect when asked to
+    let connect = || -> Result<_> {
+        // Find the device to use and connect to it
+        util::connect(select_device()?)
+    };
+
+    // Only ask before slow wireless transfers when someone is there to answer
+    let interactive = console::user_attended();
+
+    // The exit status to report once the command is done
+    let mut status = 0;
 
     // Match which command to use
-    match args.command {
-        Commands::Terminal {} => {
+    match command {
+        Commands::Terminal { options } => {
+            let mut device = connect()?;
+
             // Constantly read and print data
-            terminal(&mut device)?;
-            
-            
+            status = terminal::terminal(&mut device, &options)?;
         },
         Commands::Download { file } => {
+            let mut device = connect()?;
+
             // Download the file
-            let data = files::download_file(&mut device, file.clone())?;
+            let data = files::download_file_with(&mut device, file.clone(), DownloadOptions { interactive, ..Default::default() })?;
 
             // Write the file to disk
             std::fs::write(file, data)?;
         },
-        Commands::Upload { file } => {
+        Commands::Upload { file, after } => {
+            let mut device = connect()?;
+
             // Read the data from disk
             let data = std::fs::read(file.clone())?;
 
             // Upload the file
-            files::upload_file(&mut device, file, data)?;
+            files::upload_file_with(&mut device, file, data, UploadOptions { finished: after.into(), interactive, ..Default::default() })?;
         },
-        Commands::CargoHook { file } => {
+        Commands::Run { slot, terminal, options } => {
+            let mut device = connect()?;
 
+            // Use the project's slot unless we were told otherwise, without leaving a slot file behind
+            let slot = match slot {
+                Some(slot) => slot,
+                None => util::existing_slot()?,
+            };
 
-            // Objcopy the file to a .bin file
-            // We expect arm-none-eabi-objcopy
-            // TODO: A good idea would be to implement an objcopy alternative in rust.
-            let mut command = std::process::Command::new("arm-none-eabi-objcopy");
-            command.arg("-O").arg("binary");
-            command.arg(file.clone());
-
-            // Add the bin prefix
-            let mut upload_file = file;
-            upload_file.push_str(".bin");
-            command.arg(upload_file.clone());
-
-            // Run the command
-            command.output()?;
-
-            // Detect if the slot file exists
-            let slot_file = std::path::Path::new("slot");
-            if !slot_file.exists() {
-                // If it doesn't exist, create it, defaulting the slot number to 0
-                std::fs::write(slot_file, "0")?;
+            // Run the program file
+            device.execute_program_file(format!("slot_{}.bin", slot+1), None, None)?;
+
+            // Open terminal
+            if terminal {
+                status = terminal::terminal(&mut device, &options)?;
             }
+        },
+        Commands::Stop {} => {
+            let mut device = connect()?;
 
-            // Read in the slot file, parsing its contents into an u8
-            let slot: u8 = std::fs::read_to_string(slot_file)?.parse()?;
+            // An empty file name with the stop flag set stops whatever is running
+            device.execute_program_file("".to_string(), None, Some(util::EXECUTE_STOP))?;
+        },
+        Commands::Info {} => {
+            let mut device = connect()?;
 
-            // Try to find a Cargo.toml in the current directory
-            let cargo = std::path::Path::new("./Cargo.toml");
+            info::print_info(&mut device)?;
+        },
+        Commands::Ports { diff } => {
+            let mut device = connect()?;
+
+            let devices = ports::read_ports(&mut device)?;
+
+            if diff {
+                // The expected layout lives in the project config
+                let layout = config::read_cargo_toml()?.v5().ports
+                    .ok_or_else(|| anyhow::anyhow!("No port layout found in [package.metadata.v5.ports]"))?;
+
+                if !ports::print_diff(&devices, &layout)? {
+                    status = 1;
+                }
+            } else if output::is_json() {
+                ports::report(&devices)?;
+            } else {
+                ports::print_table(&devices);
+            }
+        },
+        Commands::Screenshot { output, gif, frames, interval } => {
+            let mut device = connect()?;
+
+            if gif {
+                let output = output.unwrap_or_else(|| "screenshot.gif".to_string());
+                screenshot::save_gif(&mut device, &output, frames, std::time::Duration::from_millis(interval))?;
+            } else {
+                let output = output.unwrap_or_else(|| "screenshot.png".to_string());
+                screenshot::save_png(&mut device, &output)?;
+            }
+        },
+        Commands::Backup { output } => {
+            let mut device = connect()?;
 
-            // If we can't find it, then we can't upload
-            if !cargo.exists() {
-                return Err(anyhow::anyhow!("Could not find Cargo.toml in the current directory"));
+            backup::backup(&mut device, output)?;
+        },
+        Commands::Restore { backup } => {
+            let mut device = connect()?;
+
+            backup::restore(&mut device, std::path::Path::new(&backup))?;
+        },
+        Commands::Firmware { options } => {
+            let mut device = connect()?;
+
+            firmware::update_firmware(&mut device, &options)?;
+        },
+        Commands::Icons {} => {
+            icons::print_icons();
+        },
+        Commands::New { path, options } => {
+            match path {
+                Some(path) if !options.list => scaffold::new(std::path::Path::new(&path), &options)?,
+                _ => scaffold::print_templates(),
+            }
+        },
+        Commands::Init { path, options } => {
+            if options.list {
+                scaffold::print_templates();
+            } else {
+                scaffold::init(std::path::Path::new(&path), &options)?;
             }
+        },
+        Commands::Build { options } => {
+            build::build(&options)?;
+        },
+        Commands::Target { options } => {
+            target::target(std::path::Path::new("."), &options)?;
+        },
+        Commands::Doctor { options } => {
+            if !doctor::doctor(&options)? {
+                status = 1;
+            }
+        },
+        Commands::Radio { channel } => {
+            let mut device = connect()?;
 
-            // Parse the toml file
-            let f = std::fs::read_to_string(cargo)?;
-            let parsed_toml = toml::from_str::<CargoToml>(&f)?;
+            match channel {
+                Some(channel) => radio::set_channel(&mut device, channel)?,
+                None => radio::print_radio(&mut device)?,
+            }
+        },
+        Commands::Name { name } => {
+            let mut device = connect()?;
+
+            match name {
+                Some(name) => system::kv_write(&mut device, system::KEY_ROBOT_NAME, &name)?,
+                None => {
+                    let value = system::kv_read(&mut device, system::KEY_ROBOT_NAME)?;
+                    if output::is_json() {
+                        output::emit(&Message::Report { command: "name", data: serde_json::json!({ "name": value }) });
+                    } else {
+                        println!("{}", value);
+                    }
+                },
+            }
+        },
+        Commands::Team { number } => {
+            let mut device = connect()?;
+
+            match number {
+                Some(number) => system::kv_write(&mut device, system::KEY_TEAM_NUMBER, &number)?,
+                None => {
+                    let value = system::kv_read(&mut device, system::KEY_TEAM_NUMBER)?;
+                    if output::is_json() {
+                        output::emit(&Message::Report { command: "team", data: serde_json::json!({ "team": value }) });
+                    } else {
+                        println!("{}", value);
+                    }
+                },
+            }
+        },
+        Commands::Test { options } => {
+            let mut device = connect()?;
+
+            // Run the tests and pass their result on to cargo
+            status = runner::run_tests(&mut device, &options)?;
+        },
+        Commands::CargoHook { file, after, no_terminal, ignore_firmware, icon, all, mut options } => {
+            // Crash reports come from the program we are about to upload
+            options.elf.get_or_insert_with(|| file.clone());
+
+            // A target spec that has drifted can build programs the brain won't run
+            target::warn_if_outdated(std::path::Path::new("."));
+
+            // Objcopy the file to a .bin file
+            let upload_file = util::objcopy(&file)?;
+
+            // Find out which slot to upload to
+            let slot = util::read_slot()?;
+
+            // Parse the project's Cargo.toml
+            let parsed_toml = config::read_cargo_toml()?;
+            let v5 = parsed_toml.v5();
+
+            // Catch programs that have outgrown their budget before spending time uploading them
+            size::check_budget(&upload_file, std::fs::metadata(&upload_file)?.len(), &v5)?;
+
+            // Command line flags take priority over the project config
+            let after = after.or(v5.after_upload).unwrap_or(AfterUpload::Run);
+            let attach_terminal = !no_terminal && v5.terminal.unwrap_or(true);
+            let icon = icons::resolve(icon.as_deref().or(v5.icon.as_deref()).unwrap_or(icons::DEFAULT_ICON))?;
 
             // Get the current time and format it as ISO 8601
             let time = std::time::SystemTime::now();
@@ -173,10 +466,7 @@ fn main() -> Result<()>{
             ini.push(format!("description = \"{}\"", parsed_toml.package.description.unwrap_or_else(|| "".to_string())));
             ini.push(format!("slot = {}", slot));
             ini.push(format!("date = {}", time));
-
-
-            // We use the Vex X logo just because.
-            ini.push("icon = USER001x.bmp".to_string());
+            ini.push(format!("icon = {}", icon));
 
             // Join into a single string
             let ini = ini.join("\n");
@@ -186,22 +476,42 @@ fn main() -> Result<()>{
             // Convert to bytes
             let ini: Vec<u8> = ini.as_bytes().to_vec();
 
-            // Upload the file
-            files::upload_file(&mut device, format!("slot_{}.ini", slot+1), ini)?;
-
             // Read in the file to upload
             let data = std::fs::read(upload_file)?;
 
-            // Upload it to the brain
-            files::upload_file(&mut device, format!("slot_{}.bin", slot+1), data)?;
-
-            // Run the program file
-            device.execute_program_file(format!("slot_{}.bin", slot+1), None, None)?;
-
-            // Open terminal
-            terminal(&mut device)?;
+            // Flash every brain at once, leaving their terminals alone
+            if all {
+                let job = flash::FlashJob { ini, data, slot, after, config: v5, check_firmware: !ignore_firmware };
+                if !flash::flash_all(&job)? {
+                    status = 1;
+                }
+            } else {
+                let mut device = connect()?;
+
+                // Make sure the brain can actually run the program
+                if !ignore_firmware {
+                    firmware::check_firmware(&mut device, &v5)?;
+                }
+
+                // Stay on the download channel from the first upload until the terminal closes,
+                // rather than switching for every file
+                status = radio::with_download_channel(&mut device, |device| {
+                    // Upload the file
+                    files::upload_file_with(device, format!("slot_{}.ini", slot+1), ini, UploadOptions { finished: VexFiletransferFinished::DoNothing, ..Default::default() })?;
+
+                    // Upload it to the brain, letting it know what to do once it is there
+                    files::upload_file_with(device, format!("slot_{}.bin", slot+1), data, UploadOptions { finished: after.into(), interactive, ..Default::default() })?;
+
+                    // Open terminal
+                    if attach_terminal {
+                        return terminal::terminal(device, &options);
+                    }
+
+                    Ok(0)
+                })?;
+            }
         }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(status)
+}
@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use console::{style, StyledObject};
+use serde::Serialize;
+
+
+/// How cargo-v5 writes its messages, like cargo's own `--message-format`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Colored text for people
+    #[default]
+    Human,
+    /// One JSON object per line for other programs
+    Json,
+}
+
+/// Whether messages are being written as JSON
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets the message format for the rest of the run
+pub fn set_message_format(format: MessageFormat) {
+    JSON.store(format == MessageFormat::Json, Ordering::Relaxed);
+}
+
+/// Returns true if messages should be written as JSON instead of text
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// A structured message written as a line of JSON when the message format is JSON.
+/// Transfers are reported separately, as [`crate::progress::TransferEvent`]s.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Message<'a> {
+    /// A vex device was found
+    DeviceFound { class: String, ports: Vec<&'a str> },
+    /// A brain was flashed by `cargo-hook --all`, successfully if there is no error
+    Flashed { device: &'a str, error: Option<String> },
+    /// A chunk of output from the program running on the brain
+    Output { text: &'a str },
+    /// A test on the brain has finished
+    Test { name: &'a str, outcome: &'a str, message: Option<&'a str> },
+    /// All tests on the brain have finished
+    TestResult { passed: usize, failed: usize, ignored: usize, seconds: f32 },
+    /// A report from a command that reads something from the brain, like `info` or `ports`
+    Report { command: &'a str, data: serde_json::Value },
+    /// Something looks wrong, but the command carries on
+    Warning { message: &'a str },
+    /// The command failed. The causes are listed from the outermost in,
+    /// and errors cargo-v5 can explain come with their kind and a hint.
+    Error { message: String, causes: Vec<String>, kind: Option<&'a str>, hint: Option<&'a str> },
+    /// The command is done, and cargo-v5 is exiting with this status
+    Finished { status: i32, seconds: f32 },
+}
+
+/// Writes a message as a line of JSON, if the message format is JSON
+pub fn emit(message: &Message) {
+    if !is_json() {
+        return;
+    }
+
+    if let Ok(line) = serde_json::to_string(message) {
+        println!("{}", line);
+    }
+}
+
+/// The mark in front of a step that succeeded
+pub fn pass_mark() -> StyledObject<&'static str> {
+    style("✔").green()
+}
+
+/// The mark in front of a step that needs attention
+pub fn warn_mark() -> StyledObject<&'static str> {
+    style("!").yellow()
+}
+
+/// The mark in front of a step that failed
+pub fn fail_mark() -> StyledObject<&'static str> {
+    style("✘").red()
+}
+
+/// Prints a warning to stderr, or emits it as a message if the message format is JSON
+pub fn warn(message: &str) {
+    if is_json() {
+        emit(&Message::Warning { message });
+    } else {
+        eprintln!("{} {}", style("Warning:").yellow().bright(), message);
+    }
+}
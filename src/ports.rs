@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, io::{Read, Write}};
+
+use anyhow::Result;
+use console::style;
+use serde::Serialize;
+use vexv5_serial::device::VexDevice;
+
+use crate::{error::Error, output::{self, Message}, system};
+
+
+/// The number of smart ports on the brain
+pub const SMART_PORTS: u8 = 21;
+
+/// Returns the name of a smart device type, as used in the project's port layout
+pub fn device_name(device_type: u8) -> &'static str {
+    match device_type {
+        0 => "none",
+        2 => "motor",
+        3 => "led",
+        4 => "rotation",
+        5 => "cr-motor",
+        6 => "imu",
+        7 => "distance",
+        8 => "radio",
+        9 => "tether",
+        10 => "brain",
+        11 => "vision",
+        12 => "adi",
+        16 => "optical",
+        17 => "magnet",
+        20 => "gps",
+        26 => "ai-camera",
+        27 => "light-tower",
+        28 => "arm",
+        29 => "ai-vision",
+        30 => "pneumatic",
+        128 => "generic",
+        129 => "serial",
+        _ => "unknown",
+    }
+}
+
+/// A device plugged into a smart port
+#[derive(Serialize, Clone, Debug)]
+pub struct PortDevice {
+    pub port: u8,
+    pub name: &'static str,
+    pub device_type: u8,
+    pub status: u8,
+    pub version: String,
+    pub boot_version: u16,
+}
+
+/// Reads what is plugged into each smart port, keyed by port number
+pub fn read_ports<T: Read + Write>(device: &mut VexDevice<T>) -> Result<BTreeMap<u8, PortDevice>> {
+    let devices = system::get_device_status(device)?;
+
+    // The brain also reports its internal devices, so only keep the smart ports
+    Ok(devices.into_iter()
+        .filter(|d| (1..=SMART_PORTS).contains(&d.port))
+        .map(|d| (d.port, PortDevice {
+            port: d.port,
+            name: device_name(d.device_type),
+            device_type: d.device_type,
+            status: d.status,
+            version: if d.beta_version != 0 {
+                format!("{}-b{}", d.version, d.beta_version)
+            } else {
+                d.version.to_string()
+            },
+            boot_version: d.boot_version,
+        }))
+        .collect())
+}
+
+/// Prints the devices on every smart port as a table
+pub fn print_table(ports: &BTreeMap<u8, PortDevice>) {
+    println!("{}", style(format!("{:<6}{:<14}{:<10}{:<10}{}", "Port", "Device", "Version", "Boot", "Status")).bold());
+    for port in 1..=SMART_PORTS {
+        match ports.get(&port) {
+            Some(d) => println!("{:<6}{:<14}{:<10}{:<10}{:#04x}", port, style(d.name).cyan(), d.version, d.boot_version, d.status),
+            None => println!("{:<6}{}", port, style("empty").black().bright()),
+        }
+    }
+}
+
+/// Emits the devices as a report message, for `--message-format json`
+pub fn report(ports: &BTreeMap<u8, PortDevice>) -> Result<()> {
+    let devices: Vec<&PortDevice> = ports.values().collect();
+    output::emit(&Message::Report { command: "ports", data: serde_json::to_value(&devices)? });
+    Ok(())
+}
+
+/// A port that doesn't match the expected layout
+#[derive(Serialize, Debug)]
+struct Difference<'a> {
+    port: u8,
+    expected: Option<&'a str>,
+    found: Option<&'a str>,
+}
+
+/// Compares the devices against the expected layout from the project config.
+/// Returns true if they match.
+pub fn print_diff(ports: &BTreeMap<u8, PortDevice>, expected: &BTreeMap<String, String>) -> Result<bool> {
+    // Parse the port numbers in the layout
+    let mut layout = BTreeMap::<u8, &str>::new();
+    for (port, name) in expected {
+        let port: u8 = port.parse()
+            .map_err(|_| Error::InvalidConfig(format!("Invalid port {:?} in the port layout", port)))?;
+        if !(1..=SMART_PORTS).contains(&port) {
+            return Err(Error::InvalidConfig(format!("Port {} in the port layout is out of range", port)).into());
+        }
+        layout.insert(port, name);
+    }
+
+    let json = output::is_json();
+    let mut differences = Vec::<Difference>::new();
+    for port in 1..=SMART_PORTS {
+        let expected = layout.get(&port).copied();
+        let found = ports.get(&port).map(|d| d.name);
+
+        if expected != found {
+            differences.push(Difference { port, expected, found });
+        }
+        if json {
+            continue;
+        }
+
+        match (expected, found) {
+            (Some(e), Some(f)) if e == f => {},
+            (None, None) => {},
+            (Some(e), Some(f)) => {
+                println!("{:<6}{} expected {}, found {}", port, style("mismatch").red(), style(e).cyan(), style(f).cyan());
+            },
+            (Some(e), None) => {
+                println!("{:<6}{} expected {}, found nothing", port, style("missing").red(), style(e).cyan());
+            },
+            (None, Some(f)) => {
+                println!("{:<6}{} found {}, not in the layout", port, style("unexpected").yellow(), style(f).cyan());
+            },
+        }
+    }
+
+    let matches = differences.is_empty();
+    if json {
+        output::emit(&Message::Report {
+            command: "ports",
+            data: serde_json::json!({ "matches": matches, "differences": differences }),
+        });
+    } else if matches {
+        println!("{} All ports match the expected layout", output::pass_mark());
+    }
+
+    Ok(matches)
+}
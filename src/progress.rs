@@ -0,0 +1,209 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use console::{style, Term};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+use crate::output;
+
+
+/// Which way a file is being transferred
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// Something that happened while transferring a file
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "event")]
+pub enum TransferEvent<'a> {
+    /// A transfer has started
+    #[serde(rename = "transfer-started")]
+    Started { file: &'a str, direction: Direction, size: u64 },
+    /// Some more of the file has been transferred
+    #[serde(rename = "transfer-progress")]
+    Progress { file: &'a str, done: u64, total: u64 },
+    /// All data has been sent and the brain is closing the file
+    #[serde(rename = "transfer-closing")]
+    Closing { file: &'a str },
+    /// The transfer is complete
+    #[serde(rename = "transfer-finished")]
+    Finished { file: &'a str, direction: Direction, size: u64, crc: u32, seconds: f32 },
+}
+
+/// Receives the events of file transfers, to show them to the user or pass them on
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: &TransferEvent);
+}
+
+/// A plain function is called with the bytes transferred so far and the total, and ignores everything else
+impl<F: Fn(u64, u64) + Send + Sync> Reporter for F {
+    fn report(&self, event: &TransferEvent) {
+        if let TransferEvent::Progress { done, total, .. } = *event {
+            self(done, total);
+        }
+    }
+}
+
+/// Picks how to show transfers: JSON events if every message is JSON,
+/// and otherwise progress bars on a terminal and plain lines elsewhere.
+/// Like cargo's own status, these go to stderr so they never mix with a program's output on stdout.
+pub fn default_reporter() -> Arc<dyn Reporter> {
+    if output::is_json() {
+        Arc::new(JsonReporter)
+    } else if Term::stderr().is_term() {
+        Arc::new(TtyReporter::default())
+    } else {
+        Arc::new(LogReporter::default())
+    }
+}
+
+
+/// Shows transfers with progress bars and spinners
+#[derive(Default)]
+pub struct TtyReporter {
+    /// The bar or spinner currently on screen
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl TtyReporter {
+    /// Creates the bar shown while data is being transferred
+    fn transfer_bar(length: u64) -> ProgressBar {
+        let bar = ProgressBar::new(length);
+
+        // Style the progress bar
+        if let Ok(bar_style) = ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {binary_bytes_per_sec} {bar:40.cyan/blue} {percent}% {bytes:>7}/{total_bytes:7} {msg}") {
+            bar.set_style(bar_style.progress_chars("##-"));
+        }
+
+        bar
+    }
+}
+
+impl Reporter for TtyReporter {
+    fn report(&self, event: &TransferEvent) {
+        let mut bar = match self.bar.lock() {
+            Ok(bar) => bar,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        match *event {
+            TransferEvent::Started { file, direction, .. } => {
+                let verb = match direction {
+                    Direction::Upload => "Uploading File",
+                    Direction::Download => "Downloading File",
+                };
+                eprintln!("{} {}", style(verb).bright(), style(file).cyan().bright());
+            },
+            TransferEvent::Progress { done, total, .. } => {
+                let current = bar.get_or_insert_with(|| Self::transfer_bar(total));
+                current.set_position(done);
+
+                // Transfers without a start or finish, like the screen buffer, only clean up here
+                if done >= total {
+                    current.finish_and_clear();
+                    *bar = None;
+                }
+            },
+            TransferEvent::Closing { .. } => {
+                if let Some(old) = bar.take() {
+                    old.finish_and_clear();
+                }
+
+                // We will also setup a spinner so the user knows that the application has not frozen.
+                let sp = ProgressBar::new_spinner();
+                sp.set_message("Closing file handle");
+                sp.enable_steady_tick(Duration::from_millis(100));
+                *bar = Some(sp);
+            },
+            TransferEvent::Finished { file, direction, seconds, .. } => {
+                if let Some(old) = bar.take() {
+                    old.finish_and_clear();
+                }
+
+                let verb = match direction {
+                    Direction::Upload => "Successfully uploaded file",
+                    Direction::Download => "Successfully downloaded file",
+                };
+
+                // Replace the line that announced the transfer
+                let _ = Term::stderr().clear_last_lines(1);
+                eprintln!("{} {} {} {}",
+                    output::pass_mark(),
+                    style(verb).bold(),
+                    style(file).cyan().bright(),
+                    style(format!("in {:.3} seconds", seconds)).bold()
+                );
+            },
+        }
+    }
+}
+
+
+/// How often, in percent, plain logs report progress
+const LOG_STEP: u64 = 25;
+
+/// Shows transfers as plain lines, for logs and other output that isn't a terminal
+#[derive(Default)]
+pub struct LogReporter {
+    /// The last percentage that was logged
+    logged: Mutex<u64>,
+}
+
+impl Reporter for LogReporter {
+    fn report(&self, event: &TransferEvent) {
+        let mut logged = match self.logged.lock() {
+            Ok(logged) => logged,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        match *event {
+            TransferEvent::Started { file, direction, size } => {
+                *logged = 0;
+                let verb = match direction {
+                    Direction::Upload => "Uploading",
+                    Direction::Download => "Downloading",
+                };
+                eprintln!("{} {} ({})", verb, file, HumanBytes(size));
+            },
+            TransferEvent::Progress { file, done, total } => {
+                let percent = (done * 100).checked_div(total).unwrap_or(100);
+                if percent >= *logged + LOG_STEP {
+                    *logged = percent - percent % LOG_STEP;
+                    eprintln!("{}: {}%", file, *logged);
+                }
+
+                // Start over for transfers that never announce themselves
+                if done >= total {
+                    *logged = 0;
+                }
+            },
+            TransferEvent::Closing { file } => {
+                eprintln!("Closing {}", file);
+            },
+            TransferEvent::Finished { file, direction, crc, seconds, .. } => {
+                let verb = match direction {
+                    Direction::Upload => "Uploaded",
+                    Direction::Download => "Downloaded",
+                };
+                eprintln!("{} {} (CRC {:08X}) in {:.3} seconds", verb, file, crc, seconds);
+            },
+        }
+    }
+}
+
+
+/// Writes every event as a line of JSON, for other programs to read
+#[derive(Default)]
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn report(&self, event: &TransferEvent) {
+        if let Ok(line) = serde_json::to_string(event) {
+            println!("{}", line);
+        }
+    }
+}
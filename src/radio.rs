@@ -0,0 +1,149 @@
+use std::{io::{Read, Write}, path::PathBuf, sync::Mutex};
+
+use anyhow::Result;
+use console::style;
+use vexv5_serial::device::{VexDevice, VexProduct};
+
+use crate::{output::{self, Message}, system::{self, RadioChannel}};
+
+
+/// The channel this process last switched the controller to.
+/// Controllers can't report which channel they are on, so this is the only way to know.
+static CHANNEL: Mutex<Option<RadioChannel>> = Mutex::new(None);
+
+/// Where the channel chosen with `cargo v5 radio --channel` is remembered between runs
+fn channel_file() -> PathBuf {
+    std::env::temp_dir().join("cargo-v5-radio-channel")
+}
+
+/// The channel the user last chose with `cargo v5 radio --channel`,
+/// or the pit channel controllers use while driving if they never chose one
+fn remembered_channel() -> RadioChannel {
+    match std::fs::read_to_string(channel_file()).as_deref().map(str::trim) {
+        Ok("download") => RadioChannel::Download,
+        _ => RadioChannel::Pit,
+    }
+}
+
+/// Switches the controller's channel and keeps track of it
+fn switch<T: Read + Write>(device: &mut VexDevice<T>, channel: RadioChannel) -> Result<()> {
+    system::switch_channel(device, channel)?;
+    *CHANNEL.lock().unwrap_or_else(|e| e.into_inner()) = Some(channel);
+
+    Ok(())
+}
+
+/// Returns true if we are talking to the brain through a controller
+pub fn through_controller<T: Read + Write>(device: &mut VexDevice<T>) -> Result<bool> {
+    Ok(matches!(device.get_device_version()?.product_type, VexProduct::V5Controller(_)))
+}
+
+/// Prints the radio's channel and link quality, and whether the controller is paired
+pub fn print_radio<T: Read + Write>(device: &mut VexDevice<T>) -> Result<()> {
+    let controller = through_controller(device)?;
+
+    // A controller that isn't paired has no brain to answer the query
+    let radio = match system::get_radio_status(device) {
+        Ok(radio) => radio,
+        Err(e) if controller => {
+            if !output::is_json() {
+                println!("  {:<12} {}", style("Controller").bold(), style("not paired to a brain").red());
+            }
+            return Err(e.context("Could not reach a brain through the controller"));
+        },
+        Err(e) => return Err(e),
+    };
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "radio", data: serde_json::json!({
+            "controller": controller,
+            "linked": radio.linked(),
+            "channel": radio.channel,
+            "timeslot": radio.timeslot,
+            "quality": radio.quality,
+            "strength": radio.strength,
+        }) });
+        return Ok(());
+    }
+
+    if controller {
+        println!("  {:<12} {}", style("Controller").bold(), if radio.linked() {
+            style("paired to a brain").green()
+        } else {
+            style("not paired to a brain").red()
+        });
+    }
+
+    println!("  {:<12} {}", style("Link").bold(), if radio.linked() { style("connected").green() } else { style("not connected").red() });
+    println!("  {:<12} {}", style("Channel").bold(), radio.channel);
+    println!("  {:<12} {}", style("Timeslot").bold(), radio.timeslot);
+    println!("  {:<12} {}", style("Quality").bold(), radio.quality);
+    println!("  {:<12} {}", style("Strength").bold(), radio.strength);
+
+    Ok(())
+}
+
+/// Switches the controller to the given radio channel
+pub fn set_channel<T: Read + Write>(device: &mut VexDevice<T>, channel: RadioChannel) -> Result<()> {
+    // Only controllers talk to the brain over the radio
+    if !through_controller(device)? {
+        return Err(anyhow::anyhow!("Radio channels can only be switched when connected through a controller"));
+    }
+
+    switch(device, channel)?;
+
+    // Remember the choice, so transfers in later runs switch back to it.
+    // Failing to remember it only means they go back to the pit channel.
+    let _ = std::fs::write(channel_file(), if channel == RadioChannel::Download { "download" } else { "pit" });
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "radio", data: serde_json::json!({ "channel": format!("{:?}", channel).to_lowercase() }) });
+    } else {
+        println!("{} {} {:?}", output::pass_mark(), style("Switched controller to the channel").bold(), channel);
+    }
+
+    Ok(())
+}
+
+/// Runs `f` with the controller switched to the download channel, so file transfers are fast.
+/// See [`with_channel`].
+pub fn with_download_channel<T, R, F>(device: &mut VexDevice<T>, f: F) -> Result<R>
+where
+    T: Read + Write,
+    F: FnOnce(&mut VexDevice<T>) -> Result<R>,
+{
+    with_channel(device, RadioChannel::Download, f)
+}
+
+/// Runs `f` with the controller switched to `channel`, then switches back to the channel it was on before,
+/// even if `f` fails. Nothing is switched if the controller is already on `channel`,
+/// so nested transfers and a terminal opened right after an upload share one switch.
+pub fn with_channel<T, R, F>(device: &mut VexDevice<T>, channel: RadioChannel, f: F) -> Result<R>
+where
+    T: Read + Write,
+    F: FnOnce(&mut VexDevice<T>) -> Result<R>,
+{
+    // Only trust a channel we switched to ourselves, since the controller may have restarted since
+    let previous = *CHANNEL.lock().unwrap_or_else(|e| e.into_inner());
+    if previous == Some(channel) {
+        return f(device);
+    }
+
+    // Brains connected over USB don't use the radio at all
+    if !through_controller(device)? {
+        return f(device);
+    }
+
+    switch(device, channel)?;
+
+    let result = f(device);
+
+    // Always switch back, but report the transfer's error over the switch's
+    let restore = previous.unwrap_or_else(remembered_channel);
+    let restored = switch(device, restore);
+    match (result, restored) {
+        (Err(e), _) => Err(e),
+        (Ok(_), Err(e)) => Err(e.context(format!("Could not switch the controller back to the {:?} channel", restore))),
+        (Ok(value), Ok(())) => Ok(value),
+    }
+}
@@ -0,0 +1,421 @@
+use std::{io::{Read, Write}, time::Instant};
+
+use anyhow::Result;
+use console::style;
+use vexv5_serial::device::{VexDevice, VexFileTarget, VexFiletransferFinished};
+
+use crate::{crash::CrashDecoder, files::{self, UploadOptions}, output::{self, Message}, radio, terminal::{self, TerminalOptions}, util};
+
+
+/// The prefix marking a line of the test protocol.
+///
+/// A test program reports its results by printing lines of the form:
+/// ```text
+/// #v5test start <count>
+/// #v5test run <name>
+/// #v5test ok <name>
+/// #v5test failed <name> [message]
+/// #v5test ignored <name>
+/// #v5test end
+/// ```
+/// Anything else printed while a test is running is captured as that test's output.
+const PROTOCOL_PREFIX: &str = "#v5test ";
+
+/// The exit status libtest uses when tests fail
+const FAILURE_STATUS: i32 = 101;
+
+/// Options for running a test binary on the brain
+#[derive(clap::Args, Debug, Clone)]
+pub struct TestOptions {
+    /// The test binary to upload
+    pub file: String,
+    /// Upload the tests to this program slot (0-7) instead of running them from RAM
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=util::MAX_SLOT as i64))]
+    pub slot: Option<u8>,
+    /// Fail the run if the tests have not finished after this many seconds
+    #[clap(long, value_name = "SECONDS", default_value_t = 60)]
+    pub timeout: u64,
+    /// Arguments passed along by cargo test. Only --nocapture and --test-threads are accepted.
+    #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+}
+
+/// Checks the arguments cargo test passed along, returning whether output should be shown as it arrives.
+/// The test program decides which tests run, so filters and options that change that are refused
+/// rather than silently ignored.
+fn parse_args(args: &[String]) -> Result<bool> {
+    let mut nocapture = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--nocapture" => nocapture = true,
+            // Tests on the brain always run one at a time
+            "--test-threads" => { args.next(); },
+            arg if arg.starts_with("--test-threads=") => {},
+            arg if arg.starts_with('-') => return Err(anyhow::anyhow!(
+                "{} is not supported when running tests on the brain. Only --nocapture and --test-threads are.", arg
+            )),
+            filter => return Err(anyhow::anyhow!(
+                "Test filters like {:?} are not supported when running tests on the brain, the test program runs every test", filter
+            )),
+        }
+    }
+
+    Ok(nocapture)
+}
+
+/// The outcome of a single test
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Failed(Option<String>),
+    Ignored,
+}
+
+/// A finished test
+#[derive(Clone, Debug)]
+struct TestResult {
+    name: String,
+    outcome: Outcome,
+    output: Vec<String>,
+}
+
+/// Collects test results as the protocol is streamed from the brain
+#[derive(Debug, Default)]
+struct TestReport {
+    /// Whether test output should be printed as it arrives
+    nocapture: bool,
+    /// Whether results are written as JSON messages instead of libtest's format
+    json: bool,
+    /// The partial line currently being received
+    line: Vec<u8>,
+    /// The test currently running and the output it has printed so far
+    current: Option<(String, Vec<String>)>,
+    /// All of the tests that have finished
+    results: Vec<TestResult>,
+    /// Whether the program reported that all tests have run
+    finished: bool,
+}
+
+impl TestReport {
+    /// Feeds a chunk of program output into the report.
+    /// Returns true once the program has reported that all tests have run.
+    fn feed(&mut self, data: &[u8]) -> bool {
+        for &byte in data {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&self.line).trim_end_matches('\r').to_string();
+            self.line.clear();
+            self.handle_line(line);
+
+            if self.finished {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Handles a single line of program output
+    fn handle_line(&mut self, line: String) {
+        // Lines that are not part of the protocol are test output
+        let message = match line.strip_prefix(PROTOCOL_PREFIX) {
+            Some(message) => message.to_string(),
+            None => {
+                if self.nocapture || self.current.is_none() {
+                    if self.json {
+                        output::emit(&Message::Output { text: &line });
+                    } else {
+                        println!("{}", line);
+                    }
+                }
+                if let Some((_, output)) = &mut self.current {
+                    output.push(line);
+                }
+                return;
+            }
+        };
+
+        // Split the message into the event, the test name and any extra detail
+        let mut parts = message.splitn(3, ' ');
+        let event = parts.next().unwrap_or_default();
+        let name = parts.next().unwrap_or_default().to_string();
+        let detail = parts.next().map(|d| d.to_string());
+
+        match event {
+            "start" if self.json => {},
+            "start" => {
+                let count = name.parse::<usize>().unwrap_or(0);
+                println!();
+                println!("running {} test{}", count, if count == 1 { "" } else { "s" });
+            },
+            "run" => {
+                self.current = Some((name, Vec::new()));
+            },
+            "ok" => self.finish(name, Outcome::Ok),
+            "failed" => self.finish(name, Outcome::Failed(detail)),
+            "ignored" => self.finish(name, Outcome::Ignored),
+            "end" => {
+                self.finished = true;
+            },
+            _ if self.json => {},
+            _ => {
+                println!("{} unknown test event {:?}", style("warning:").yellow().bold(), line);
+            }
+        }
+    }
+
+    /// Records the outcome of a test, printing it in libtest's format
+    fn finish(&mut self, name: String, outcome: Outcome) {
+        // Take the captured output if this is the test we saw start
+        let output = match self.current.take() {
+            Some((current, output)) if current == name => output,
+            _ => Vec::new(),
+        };
+
+        self.print_outcome(&name, &outcome);
+
+        self.results.push(TestResult { name, outcome, output });
+    }
+
+    /// Prints the outcome of a test in libtest's format, or as a message
+    fn print_outcome(&self, name: &str, outcome: &Outcome) {
+        if self.json {
+            let (outcome, message) = match outcome {
+                Outcome::Ok => ("ok", None),
+                Outcome::Failed(message) => ("failed", message.as_deref()),
+                Outcome::Ignored => ("ignored", None),
+            };
+            output::emit(&Message::Test { name, outcome, message });
+            return;
+        }
+
+        let status = match outcome {
+            Outcome::Ok => style("ok").green(),
+            Outcome::Failed(_) => style("FAILED").red(),
+            Outcome::Ignored => style("ignored").yellow(),
+        };
+        println!("test {} ... {}", name, status);
+    }
+
+    /// Prints the failures and summary line, returning the exit status for the run
+    fn summarize(mut self, elapsed: f32, exit_status: i32) -> i32 {
+        // A test that never reported back failed, most likely by crashing the program
+        if let Some((name, output)) = self.current.take() {
+            let reason = if exit_status == terminal::TIMEOUT_STATUS {
+                "test timed out"
+            } else {
+                "program stopped before the test finished"
+            };
+            let outcome = Outcome::Failed(Some(reason.to_string()));
+            self.print_outcome(&name, &outcome);
+            self.results.push(TestResult { name, outcome, output });
+        }
+
+        let failures: Vec<&TestResult> = self.results.iter()
+            .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+            .collect();
+        let passed = self.results.iter().filter(|r| r.outcome == Outcome::Ok).count();
+        let ignored = self.results.iter().filter(|r| r.outcome == Outcome::Ignored).count();
+
+        // The run also fails if the program never said it was done
+        let ok = failures.is_empty() && self.finished;
+
+        if self.json {
+            output::emit(&Message::TestResult { passed, failed: failures.len(), ignored, seconds: elapsed });
+            return if ok { 0 } else { FAILURE_STATUS };
+        }
+
+        // List the output of every failed test
+        if !failures.is_empty() {
+            println!();
+            println!("failures:");
+            for failure in &failures {
+                println!();
+                println!("---- {} stdout ----", failure.name);
+                for line in &failure.output {
+                    println!("{}", line);
+                }
+                if let Outcome::Failed(Some(message)) = &failure.outcome {
+                    println!("{}", message);
+                }
+            }
+
+            println!();
+            println!("failures:");
+            for failure in &failures {
+                println!("    {}", failure.name);
+            }
+        }
+
+        if !self.finished {
+            println!();
+            println!("{} the test program exited before reporting that all tests had run", style("error:").red().bold());
+        }
+
+        // Filters are refused and benchmarks aren't run, so nothing is ever measured or filtered out
+        println!();
+        println!("test result: {}. {} passed; {} failed; {} ignored; 0 measured; 0 filtered out; finished in {:.2}s",
+            if ok { style("ok").green() } else { style("FAILED").red() },
+            passed,
+            failures.len(),
+            ignored,
+            elapsed,
+        );
+        println!();
+
+        if ok { 0 } else { FAILURE_STATUS }
+    }
+}
+
+/// Uploads a test binary to the brain, runs it and reports the results.
+/// Returns the exit status cargo test should see.
+pub fn run_tests<T: Read + Write>(device: &mut VexDevice<T>, options: &TestOptions) -> Result<i32> {
+    // Refuse arguments we can't honour before uploading anything
+    let nocapture = parse_args(&options.args)?;
+
+    // Objcopy the file to a .bin file
+    let upload_file = util::objcopy(&options.file)?;
+    let data = std::fs::read(upload_file)?;
+
+    // Stay on the download channel from the upload until the results are in
+    radio::with_download_channel(device, |device| {
+        match options.slot {
+            Some(slot) => {
+                // Upload to flash and start it like any other program
+                files::upload_file(device, format!("slot_{}.bin", slot+1), data)?;
+                device.execute_program_file(format!("slot_{}.bin", slot+1), None, None)?;
+            },
+            None => {
+                // Load into RAM and let the brain run it as soon as the transfer finishes
+                files::upload_file_with(device, "test.bin".to_string(), data, UploadOptions {
+                    target: VexFileTarget::DDR,
+                    finished: VexFiletransferFinished::RunProgram,
+                    ..Default::default()
+                })?;
+            }
+        }
+
+        // Begin timer
+        let time = Instant::now();
+
+        let mut report = TestReport {
+            nocapture,
+            json: output::is_json(),
+            ..Default::default()
+        };
+
+        // Stream the program output into the report until it says it is done
+        let terminal_options = TerminalOptions {
+            timeout: Some(options.timeout),
+            exit_on_stop: true,
+            ..Default::default()
+        };
+        let mut decoder = CrashDecoder::new(&options.file);
+        let status = terminal::stream(device, &terminal_options, |data| {
+            decoder.feed(data);
+            Ok(if report.feed(data) { Some(0) } else { None })
+        })?;
+
+        std::io::stdout().flush()?;
+
+        Ok(report.summarize(time.elapsed().as_secs_f32(), status))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_lines(report: &mut TestReport, lines: &[&str]) -> bool {
+        report.feed(lines.iter().map(|l| format!("{}\n", l)).collect::<String>().as_bytes())
+    }
+
+    #[test]
+    fn records_each_outcome() {
+        let mut report = TestReport::default();
+        let finished = feed_lines(&mut report, &[
+            "#v5test start 3",
+            "#v5test run passes",
+            "#v5test ok passes",
+            "#v5test run fails",
+            "#v5test failed fails assertion failed",
+            "#v5test ignored skipped",
+            "#v5test end",
+        ]);
+
+        assert!(finished);
+        assert!(report.finished);
+        let outcomes: Vec<(&str, &Outcome)> = report.results.iter().map(|r| (r.name.as_str(), &r.outcome)).collect();
+        assert_eq!(outcomes, [
+            ("passes", &Outcome::Ok),
+            ("fails", &Outcome::Failed(Some("assertion failed".to_string()))),
+            ("skipped", &Outcome::Ignored),
+        ]);
+        assert_eq!(report.summarize(0.0, 0), FAILURE_STATUS);
+    }
+
+    #[test]
+    fn stops_feeding_at_end() {
+        let mut report = TestReport::default();
+        assert!(!feed_lines(&mut report, &["#v5test start 1", "#v5test run a"]));
+        assert!(!report.feed(b"#v5test ok a\n#v5test e"));
+        assert!(report.feed(b"nd\r\n"));
+        assert_eq!(report.summarize(0.0, 0), 0);
+    }
+
+    #[test]
+    fn captures_output_of_running_test() {
+        let mut report = TestReport::default();
+        feed_lines(&mut report, &[
+            "#v5test run noisy",
+            "first line",
+            "second line",
+            "#v5test failed noisy",
+            "#v5test end",
+        ]);
+
+        assert_eq!(report.results[0].output, ["first line", "second line"]);
+        assert_eq!(report.results[0].outcome, Outcome::Failed(None));
+    }
+
+    #[test]
+    fn fails_test_that_crashed() {
+        let mut report = TestReport::default();
+        feed_lines(&mut report, &["#v5test start 2", "#v5test run a", "#v5test ok a", "#v5test run b"]);
+
+        assert!(!report.finished);
+        assert_eq!(report.current.as_ref().map(|(name, _)| name.as_str()), Some("b"));
+        assert_eq!(report.summarize(0.0, 0), FAILURE_STATUS);
+    }
+
+    #[test]
+    fn fails_run_that_never_ends() {
+        let mut report = TestReport::default();
+        feed_lines(&mut report, &["#v5test start 1", "#v5test run a", "#v5test ok a"]);
+
+        assert_eq!(report.summarize(0.0, 0), FAILURE_STATUS);
+    }
+
+    #[test]
+    fn accepts_nocapture_and_test_threads() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        assert!(!parse_args(&[]).unwrap());
+        assert!(parse_args(&args(&["--nocapture"])).unwrap());
+        assert!(parse_args(&args(&["--test-threads", "1", "--nocapture"])).unwrap());
+        assert!(!parse_args(&args(&["--test-threads=4"])).unwrap());
+    }
+
+    #[test]
+    fn refuses_filters_and_unknown_options() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        assert!(parse_args(&args(&["some_test"])).is_err());
+        assert!(parse_args(&args(&["--exact", "some_test"])).is_err());
+        assert!(parse_args(&args(&["--ignored"])).is_err());
+    }
+}
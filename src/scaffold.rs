@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use anyhow::Result;
+use console::style;
+use serde::Serialize;
+
+use crate::{error::Error, icons, output::{self, Message}, target};
+
+
+/// A starting point for a new project
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The dependencies added to the project's Cargo.toml
+    pub dependencies: &'static [(&'static str, &'static str)],
+    /// The contents of src/main.rs
+    pub main: &'static str,
+    /// Whether the program is linked with our linker script, rather than one from a runtime crate
+    pub linker_script: bool,
+}
+
+/// The templates `new` and `init` can start from
+pub const TEMPLATES: [Template; 2] = [
+    Template {
+        name: "bare",
+        description: "A no_std program with no runtime, that starts itself and loops forever",
+        dependencies: &[],
+        main: include_str!("../templates/bare/main.rs"),
+        linker_script: true,
+    },
+    Template {
+        name: "vex-rt",
+        description: "A robot built on the vex-rt runtime",
+        dependencies: &[("vex-rt", "0.15")],
+        main: include_str!("../templates/vex-rt/main.rs"),
+        linker_script: false,
+    },
+];
+
+/// The template used when none is chosen
+pub const DEFAULT_TEMPLATE: &str = "bare";
+
+/// Options for creating a project
+#[derive(clap::Args, Debug, Clone)]
+pub struct ScaffoldOptions {
+    /// The template to start from. See --list for the available templates.
+    #[clap(long, default_value = DEFAULT_TEMPLATE)]
+    pub template: String,
+    /// The name of the package. Defaults to the directory name.
+    #[clap(long)]
+    pub name: Option<String>,
+    /// List the available templates instead of creating a project
+    #[clap(long)]
+    pub list: bool,
+}
+
+/// Prints the available templates
+pub fn print_templates() {
+    if output::is_json() {
+        let templates: Vec<_> = TEMPLATES.iter()
+            .map(|t| serde_json::json!({ "name": t.name, "description": t.description }))
+            .collect();
+        output::emit(&Message::Report { command: "templates", data: serde_json::Value::Array(templates) });
+        return;
+    }
+
+    for template in &TEMPLATES {
+        println!("{:<10}{}", style(template.name).cyan(), template.description);
+    }
+}
+
+/// Finds a template by name
+fn find_template(name: &str) -> Result<&'static Template> {
+    TEMPLATES.iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown template {:?}. Run `cargo v5 new --list` to see the available templates.", name))
+}
+
+/// Checks that a name can be used as a package name
+fn check_name(name: &str) -> Result<()> {
+    let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && name.chars().next().is_some_and(|c| c.is_ascii_alphabetic());
+
+    if !valid {
+        return Err(Error::InvalidConfig(format!(
+            "{:?} is not a valid package name. Use letters, numbers, - and _, starting with a letter.", name
+        )).into());
+    }
+
+    Ok(())
+}
+
+/// The `[package.metadata.v5]` table new projects start with
+fn metadata_table() -> String {
+    [
+        "[package.metadata.v5]",
+        "# What the brain does once the program is uploaded: \"none\", \"show-run-screen\", \"run\" or \"halt\"",
+        "after-upload = \"run\"",
+        "# Whether cargo-hook opens the terminal after uploading",
+        "terminal = true",
+        "# The program icon shown on the brain, by name or number. See `cargo v5 icons`.",
+        &format!("icon = \"{}\"", icons::DEFAULT_ICON),
+        "",
+    ].join("\n")
+}
+
+/// The project's Cargo.toml
+fn cargo_toml(name: &str, template: &Template) -> String {
+    let mut lines = vec![
+        "[package]".to_string(),
+        format!("name = \"{}\"", name),
+        "version = \"0.1.0\"".to_string(),
+        "edition = \"2021\"".to_string(),
+        "".to_string(),
+        "[dependencies]".to_string(),
+    ];
+    for (dependency, version) in template.dependencies {
+        lines.push(format!("{} = \"{}\"", dependency, version));
+    }
+    lines.extend([
+        "".to_string(),
+        "[profile.dev]".to_string(),
+        "panic = \"abort\"".to_string(),
+        "".to_string(),
+        "[profile.release]".to_string(),
+        "panic = \"abort\"".to_string(),
+        "opt-level = \"s\"".to_string(),
+        "lto = true".to_string(),
+        "".to_string(),
+    ]);
+
+    lines.join("\n") + &metadata_table()
+}
+
+/// The project's .cargo/config.toml, which builds for the brain and runs programs with cargo-v5
+fn cargo_config(template: &Template) -> String {
+    let mut lines = vec![
+        "[build]".to_string(),
+        format!("target = \"{}/{}\"", target::TARGET_DIR, target::TARGET_SPEC_FILE),
+        "".to_string(),
+        "[unstable]".to_string(),
+        "build-std-features = [\"compiler-builtins-mem\", \"compiler-builtins-mangled-names\"]".to_string(),
+        "build-std = [\"core\", \"alloc\", \"compiler_builtins\"]".to_string(),
+        "".to_string(),
+        "[target.armv7a-vex-eabi]".to_string(),
+        "runner = \"cargo v5 cargo-hook\"".to_string(),
+    ];
+    if template.linker_script {
+        lines.push(format!("rustflags = [\"-C\", \"link-arg=-T{}/{}\"]", target::TARGET_DIR, target::LINKER_SCRIPT_FILE));
+    }
+    lines.push("".to_string());
+
+    lines.join("\n")
+}
+
+/// What setting up a project did to one of its files
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// The file was written
+    Created,
+    /// The file already existed and was left alone
+    Skipped,
+    /// Our settings were added to the file
+    Updated,
+    /// The file already had our settings
+    Unchanged,
+}
+
+/// A file of the project, and what was done to it
+#[derive(Serialize, Clone, Debug)]
+pub struct FileResult {
+    pub file: String,
+    pub action: Action,
+}
+
+/// Writes a file unless it already exists, so `init` never overwrites anything
+fn write_new(dir: &Path, file: &str, contents: &str) -> Result<FileResult> {
+    let path = dir.join(file);
+    if path.exists() {
+        return Ok(FileResult { file: file.to_string(), action: Action::Skipped });
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, contents)?;
+
+    Ok(FileResult { file: file.to_string(), action: Action::Created })
+}
+
+/// Writes the project's files into a directory, leaving any that already exist alone
+fn scaffold(dir: &Path, template: &Template, options: &ScaffoldOptions) -> Result<Vec<FileResult>> {
+    std::fs::create_dir_all(dir)?;
+    let mut results = Vec::new();
+
+    // An existing Cargo.toml just needs our settings
+    let manifest = dir.join("Cargo.toml");
+    if manifest.exists() {
+        let contents = std::fs::read_to_string(&manifest)?;
+        let action = if contents.contains("[package.metadata.v5]") {
+            Action::Unchanged
+        } else {
+            std::fs::write(&manifest, format!("{}\n\n{}", contents.trim_end(), metadata_table()))?;
+            Action::Updated
+        };
+        results.push(FileResult { file: "Cargo.toml".to_string(), action });
+    } else {
+        // Default to the directory's name, like cargo does
+        let name = match &options.name {
+            Some(name) => name.clone(),
+            None => dir.canonicalize()?
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .ok_or_else(|| anyhow::anyhow!("Could not work out a package name for {}. Pass one with --name.", dir.display()))?,
+        };
+        check_name(&name)?;
+
+        results.push(write_new(dir, "Cargo.toml", &cargo_toml(&name, template))?);
+    }
+
+    results.push(write_new(dir, ".cargo/config.toml", &cargo_config(template))?);
+    results.push(write_new(dir, &format!("{}/{}", target::TARGET_DIR, target::TARGET_SPEC_FILE), target::TARGET_SPEC)?);
+    if template.linker_script {
+        results.push(write_new(dir, &format!("{}/{}", target::TARGET_DIR, target::LINKER_SCRIPT_FILE), target::LINKER_SCRIPT)?);
+    }
+    results.push(write_new(dir, "src/main.rs", template.main)?);
+    results.push(write_new(dir, ".gitignore", "/target\n/slot\n*.bin\n")?);
+
+    Ok(results)
+}
+
+/// Reports what was done to each file of a new or initialized project
+fn report(command: &str, dir: &Path, template: &Template, results: &[FileResult]) -> Result<()> {
+    if output::is_json() {
+        output::emit(&Message::Report { command, data: serde_json::json!({
+            "path": dir,
+            "template": template.name,
+            "files": results,
+        }) });
+        return Ok(());
+    }
+
+    for result in results {
+        match result.action {
+            Action::Created => println!("{} {}", style("Created").green().bright(), result.file),
+            Action::Skipped => println!("{} {} already exists", style("Skipping").yellow().bright(), result.file),
+            Action::Updated => println!("{} [package.metadata.v5] to {}", style("Added").green().bright(), result.file),
+            Action::Unchanged => println!("{} {} already has [package.metadata.v5]", style("Skipping").yellow().bright(), result.file),
+        }
+    }
+
+    println!("{} {} {} {}",
+        output::pass_mark(),
+        style("Set up").bold(),
+        style(dir.display()).cyan().bright(),
+        style(format!("from the {} template", template.name)).bold()
+    );
+
+    Ok(())
+}
+
+/// Sets up a V5 project in a directory, leaving any files that already exist alone
+pub fn init(dir: &Path, options: &ScaffoldOptions) -> Result<()> {
+    let template = find_template(&options.template)?;
+    let results = scaffold(dir, template, options)?;
+
+    report("init", dir, template, &results)
+}
+
+/// Creates a new V5 project in a new directory
+pub fn new(path: &Path, options: &ScaffoldOptions) -> Result<()> {
+    if path.exists() {
+        return Err(anyhow::anyhow!("{} already exists. Use `cargo v5 init` to set up an existing directory.", path.display()));
+    }
+
+    let template = find_template(&options.template)?;
+    let results = scaffold(path, template, options)?;
+
+    report("new", path, template, &results)
+}
@@ -0,0 +1,102 @@
+use std::{io::{Read, Write}, time::{Duration, Instant}};
+
+use anyhow::Result;
+use console::style;
+use vexv5_serial::device::{VexDevice, VexFileTarget, VexVID};
+
+use crate::{files, output::{self, Message}, system::{self, ExtendedCommand}};
+
+
+/// The width of the visible screen
+pub const WIDTH: u32 = 480;
+/// The height of the screen
+pub const HEIGHT: u32 = 272;
+/// The width of each row in the framebuffer, which is wider than the screen
+const STRIDE: u32 = 512;
+
+/// Captures the brain's screen, returning it as packed RGB pixels
+pub fn capture<T: Read + Write>(device: &mut VexDevice<T>) -> Result<Vec<u8>> {
+    // Ask the brain to copy its screen into the capture buffer
+    system::extended_command(device, ExtendedCommand::ScreenCapture, Vec::new())?;
+
+    // Then read the buffer out over the file transfer channel
+    let length = STRIDE * HEIGHT * 4;
+    let data = files::download_raw(device, "".to_string(), VexFileTarget::SCREEN, VexVID::SYSTEM, length, 0)?;
+
+    // The brain decides how much it sends, so don't trust it to be a whole frame
+    if data.len() < length as usize {
+        return Err(anyhow::anyhow!("The brain sent {} bytes of screen data, but a full screen is {} bytes", data.len(), length));
+    }
+
+    // Each pixel is a little endian ARGB word, so the bytes are in BGRA order
+    let mut pixels = Vec::with_capacity((WIDTH * HEIGHT * 3) as usize);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let i = ((y * STRIDE + x) * 4) as usize;
+            pixels.extend_from_slice(&[data[i + 2], data[i + 1], data[i]]);
+        }
+    }
+
+    Ok(pixels)
+}
+
+/// Captures the brain's screen and writes it to a PNG file
+pub fn save_png<T: Read + Write>(device: &mut VexDevice<T>, path: &str) -> Result<()> {
+    let pixels = capture(device)?;
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, WIDTH, HEIGHT);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "screenshot", data: serde_json::json!({ "path": path, "frames": 1 }) });
+    } else {
+        println!("{} {} {}", output::pass_mark(), style("Saved screenshot to").bold(), style(path).cyan().bright());
+    }
+
+    Ok(())
+}
+
+/// Captures the brain's screen repeatedly and writes the captures to an animated GIF
+pub fn save_gif<T: Read + Write>(device: &mut VexDevice<T>, path: &str, frames: u32, interval: Duration) -> Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = gif::Encoder::new(file, WIDTH as u16, HEIGHT as u16, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    // Reading the screen takes a while, so capture everything first and then
+    // time each frame by how long it actually took to get the next one.
+    let mut captures = Vec::<(Vec<u8>, Instant)>::new();
+    for i in 0..frames {
+        if !output::is_json() {
+            println!("{} {}/{}", style("Capturing frame").bright(), i + 1, frames);
+        }
+        captures.push((capture(device)?, Instant::now()));
+
+        if i + 1 < frames {
+            std::thread::sleep(interval);
+        }
+    }
+
+    for (i, (pixels, time)) in captures.iter().enumerate() {
+        // The last frame is shown for one interval
+        let shown_for = match captures.get(i + 1) {
+            Some((_, next)) => next.duration_since(*time),
+            None => interval,
+        };
+
+        let mut frame = gif::Frame::from_rgb_speed(WIDTH as u16, HEIGHT as u16, pixels, 10);
+        // GIF delays are in hundredths of a second
+        frame.delay = (shown_for.as_millis() / 10).min(u16::MAX as u128) as u16;
+        encoder.write_frame(&frame)?;
+    }
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "screenshot", data: serde_json::json!({ "path": path, "frames": frames }) });
+    } else {
+        println!("{} {} {}", output::pass_mark(), style(format!("Saved {} frames to", frames)).bold(), style(path).cyan().bright());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use addr2line::object::{elf, Object, ObjectSection, ObjectSymbol, SectionFlags, SectionKind};
+use anyhow::Result;
+use console::style;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::V5Metadata, error::Error, output};
+
+
+/// The memory VEXos gives a user program, the USER_RAM region of the bundled linker script
+pub const USER_MEMORY: u64 = 0x0480_0000;
+
+/// How many of the largest symbols and crates `cargo v5 build` shows by default
+pub const DEFAULT_TOP: usize = 10;
+
+/// What to do when a program is over its size budget
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizePolicy {
+    /// Print a warning and upload anyway
+    Warn,
+    /// Refuse to upload
+    #[default]
+    Refuse,
+}
+
+/// A size budget from the project config, either a number of bytes or a size like "2 MiB"
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SizeLimit {
+    Bytes(u64),
+    Text(String),
+}
+
+impl SizeLimit {
+    /// Returns the budget in bytes
+    pub fn bytes(&self) -> Result<u64> {
+        match self {
+            SizeLimit::Bytes(bytes) => Ok(*bytes),
+            SizeLimit::Text(text) => parse_size(text),
+        }
+    }
+}
+
+/// Parses a size such as `1500000`, `512 KiB`, `1.5MiB` or `2 MB`
+fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "kb" => 1000,
+        "mb" => 1000 * 1000,
+        _ => return Err(Error::InvalidConfig(format!("Unknown unit in max-size {:?}. Use B, KiB, MiB, KB or MB.", text)).into()),
+    };
+    let number: f64 = number.parse()
+        .map_err(|_| Error::InvalidConfig(format!("max-size {:?} is not a size", text)))?;
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// A section of the program that takes up memory on the brain
+#[derive(Serialize, Clone, Debug)]
+pub struct Section {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    /// Whether the section is stored in the .bin, rather than zeroed or reserved at startup
+    pub loaded: bool,
+}
+
+/// Lists the sections of an ELF that are allocated in memory, in address order
+pub fn sections(elf: &Path) -> Result<Vec<Section>> {
+    let data = std::fs::read(elf)?;
+    let object = addr2line::object::File::parse(&*data)?;
+
+    let mut sections: Vec<Section> = object.sections()
+        .filter(|section| match section.flags() {
+            SectionFlags::Elf { sh_flags } => sh_flags & u64::from(elf::SHF_ALLOC) != 0,
+            _ => false,
+        })
+        .filter(|section| section.size() > 0)
+        .map(|section| Section {
+            name: section.name().unwrap_or("?").to_string(),
+            address: section.address(),
+            size: section.size(),
+            loaded: section.kind() != SectionKind::UninitializedData,
+        })
+        .collect();
+    sections.sort_by_key(|s| s.address);
+
+    Ok(sections)
+}
+
+/// Prints each section's size and how much of the program's memory it uses
+pub fn print_sections(sections: &[Section]) {
+    println!("{}", style(format!("{:<16}{:>12}{:>12}{:>9}", "Section", "Address", "Size", "Memory")).bold());
+    for section in sections {
+        println!("{:<16}{:>12}{:>12}{:>8.1}%{}",
+            section.name,
+            format!("{:#010x}", section.address),
+            HumanBytes(section.size).to_string(),
+            section.size as f64 / USER_MEMORY as f64 * 100.0,
+            if section.loaded { "" } else { "  (not in .bin)" },
+        );
+    }
+
+    let used: u64 = sections.iter().map(|s| s.size).sum();
+    println!("{:<16}{:>12}{:>12}{:>8.1}%",
+        style("Total").bold(),
+        "",
+        HumanBytes(used).to_string(),
+        used as f64 / USER_MEMORY as f64 * 100.0,
+    );
+}
+
+/// A function or static in the program
+#[derive(Serialize, Clone, Debug)]
+pub struct Symbol {
+    /// The demangled name
+    pub name: String,
+    /// The crate the symbol most likely came from
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub size: u64,
+}
+
+/// Guesses which crate a demangled symbol came from, like cargo-bloat does.
+/// Trait impls demangle as `<Type as Trait>::method`, so they count towards the type's crate.
+fn crate_of(name: &str) -> String {
+    let path = name.trim_start_matches(['<', '&', '*'])
+        .trim_start_matches("mut ")
+        .trim_start_matches("const ")
+        .trim_start_matches("dyn ");
+
+    match path.split_once("::") {
+        Some((krate, _)) if !krate.is_empty() && krate.chars().all(|c| c.is_alphanumeric() || c == '_') => krate.to_string(),
+        _ => "[unknown]".to_string(),
+    }
+}
+
+/// Lists the functions and statics of an ELF, largest first
+pub fn symbols(elf: &Path) -> Result<Vec<Symbol>> {
+    let data = std::fs::read(elf)?;
+    let object = addr2line::object::File::parse(&*data)?;
+
+    let mut symbols: Vec<Symbol> = object.symbols()
+        .filter(|symbol| symbol.is_definition() && symbol.size() > 0)
+        .filter_map(|symbol| {
+            let name = addr2line::demangle_auto(symbol.name().ok()?.into(), None).to_string();
+            Some(Symbol { krate: crate_of(&name), name, size: symbol.size() })
+        })
+        .collect();
+    symbols.sort_by_key(|s| std::cmp::Reverse(s.size));
+
+    Ok(symbols)
+}
+
+/// Adds up the size of each crate's symbols, largest first
+pub fn crates(symbols: &[Symbol]) -> Vec<(String, u64)> {
+    let mut crates = std::collections::BTreeMap::<&str, u64>::new();
+    for symbol in symbols {
+        *crates.entry(&symbol.krate).or_default() += symbol.size;
+    }
+
+    let mut crates: Vec<(String, u64)> = crates.into_iter().map(|(k, size)| (k.to_string(), size)).collect();
+    crates.sort_by_key(|c| std::cmp::Reverse(c.1));
+    crates
+}
+
+/// Prints the largest crates and symbols, with their share of the program's code and data
+pub fn print_largest(symbols: &[Symbol], top: usize) {
+    let total: u64 = symbols.iter().map(|s| s.size).sum::<u64>().max(1);
+
+    println!();
+    println!("{}", style(format!("{:>12}{:>8}  {}", "Size", "Share", "Crate")).bold());
+    for (krate, size) in crates(symbols).iter().take(top) {
+        println!("{:>12}{:>7.1}%  {}", HumanBytes(*size).to_string(), *size as f64 / total as f64 * 100.0, krate);
+    }
+
+    println!();
+    println!("{}", style(format!("{:>12}{:>8}  {}", "Size", "Share", "Symbol")).bold());
+    for symbol in symbols.iter().take(top) {
+        println!("{:>12}{:>7.1}%  {}", HumanBytes(symbol.size).to_string(), symbol.size as f64 / total as f64 * 100.0, symbol.name);
+    }
+}
+
+/// Checks a program's .bin against the project's size budget, if it has one.
+/// Depending on the size policy, a program over budget is an error or a warning.
+pub fn check_budget(file: &str, size: u64, config: &V5Metadata) -> Result<()> {
+    let budget = match &config.max_size {
+        Some(limit) => limit.bytes()?,
+        None => return Ok(()),
+    };
+    if size <= budget {
+        return Ok(());
+    }
+
+    let error = Error::TooLarge { file: file.to_string(), size, budget };
+    match config.size_policy.unwrap_or_default() {
+        SizePolicy::Warn => {
+            output::warn(&error.to_string());
+            Ok(())
+        },
+        SizePolicy::Refuse => Err(error.into()),
+    }
+}
+
+/// Prints how much of the project's size budget a program uses
+pub fn print_budget(size: u64, config: &V5Metadata) -> Result<()> {
+    if let Some(limit) = &config.max_size {
+        let budget = limit.bytes()?;
+        let used = size as f64 / budget as f64 * 100.0;
+        let used = if size > budget { style(format!("{:.1}%", used)).red().bright() } else { style(format!("{:.1}%", used)).green() };
+        println!("{} of the {} budget used", used, HumanBytes(budget));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("1500000").unwrap(), 1_500_000);
+        assert_eq!(parse_size("512 KiB").unwrap(), 512 * 1024);
+        assert_eq!(parse_size("1.5MiB").unwrap(), 1536 * 1024);
+        assert_eq!(parse_size("2 MB").unwrap(), 2_000_000);
+        assert_eq!(parse_size("64kb").unwrap(), 64_000);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        for text in ["3 GB", "12 bytes", "MiB", "1.2.3 KiB", ""] {
+            assert!(parse_size(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn attributes_symbols_to_crates() {
+        assert_eq!(crate_of("core::fmt::write"), "core");
+        assert_eq!(crate_of("my_robot::drive::tank"), "my_robot");
+        assert_eq!(crate_of("<alloc::vec::Vec<u8> as core::fmt::Debug>::fmt"), "alloc");
+        assert_eq!(crate_of("<&mut vex_rt::io::Serial as core::fmt::Write>::write_str"), "vex_rt");
+        assert_eq!(crate_of("<dyn core::any::Any>::is"), "core");
+        assert_eq!(crate_of("memcpy"), "[unknown]");
+        assert_eq!(crate_of("<T as Trait>::m"), "[unknown]");
+    }
+}
@@ -0,0 +1,447 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use vexv5_serial::device::{VexDevice, V5ControllerChannel};
+
+
+/// Extended command ids for queries that vexv5_serial does not wrap itself.
+/// These are the same ids that PROS uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExtendedCommand {
+    FileControl = 0x10,
+    GetDirectoryCount = 0x16,
+    GetMetadataByIndex = 0x17,
+    GetSystemFlags = 0x20,
+    GetDeviceStatus = 0x21,
+    GetSystemStatus = 0x22,
+    GetRadioStatus = 0x26,
+    ScreenCapture = 0x28,
+    ReadKeyValue = 0x2E,
+    WriteKeyValue = 0x2F,
+}
+
+/// Sends an extended command to the brain and returns the payload of its reply
+pub fn extended_command<T: Read + Write>(device: &mut VexDevice<T>, command: ExtendedCommand, payload: Vec<u8>) -> Result<Vec<u8>> {
+    // Send the command
+    device.send_extended(command as u8, payload)?;
+
+    // And wait for the reply
+    let (_, data) = device.receive_extended()?;
+
+    Ok(data)
+}
+
+/// A cursor for reading little endian values out of a reply
+struct Reply<'a> {
+    command: ExtendedCommand,
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reply<'a> {
+    fn new(command: ExtendedCommand, data: &'a [u8]) -> Self {
+        Self { command, data, offset: 0 }
+    }
+
+    /// Takes the next `len` bytes of the reply
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset + len)
+            .ok_or_else(|| anyhow::anyhow!("Reply to {:?} is too short ({} bytes)", self.command, self.data.len()))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        let b = self.bytes(2)?;
+        Ok(i16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads a fixed length, null padded string
+    fn string(&mut self, len: usize) -> Result<String> {
+        let b = self.bytes(len)?;
+        let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+        Ok(String::from_utf8_lossy(&b[..end]).to_string())
+    }
+}
+
+
+/// The system flags reported by the brain
+#[derive(Clone, Copy, Debug)]
+pub struct SystemFlags {
+    /// The raw flag bits
+    pub flags: u32,
+    /// The brain's battery percentage
+    pub battery: u8,
+    /// The connected controller's battery percentage
+    pub controller_battery: u8,
+    /// The radio link quality as a percentage
+    pub radio_quality: u8,
+    /// The partner controller's battery percentage
+    pub partner_battery: u8,
+    /// The slot of the currently running program, if any
+    pub current_program: Option<u8>,
+}
+
+/// Reads the system flags from the brain
+pub fn get_system_flags<T: Read + Write>(device: &mut VexDevice<T>) -> Result<SystemFlags> {
+    let data = extended_command(device, ExtendedCommand::GetSystemFlags, Vec::new())?;
+    let mut reply = Reply::new(ExtendedCommand::GetSystemFlags, &data);
+
+    // The reply is a 32 bit flag field followed by three status bytes
+    let flags = reply.u32()?;
+    let batteries = reply.u8()?;
+    let radio = reply.u8()?;
+    let current_program = reply.u8()?;
+
+    // The battery percentages are packed into nibbles in steps of 8%
+    Ok(SystemFlags {
+        flags,
+        battery: (batteries >> 4) * 8,
+        controller_battery: (batteries & 0xf) * 8,
+        radio_quality: (radio >> 4) * 8,
+        partner_battery: (radio & 0xf) * 8,
+        // Zero means no program is running
+        current_program: match current_program {
+            0 => None,
+            slot => Some(slot),
+        },
+    })
+}
+
+
+/// The status of a device plugged into one of the brain's ports
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceStatus {
+    /// The port the device is plugged into
+    pub port: u8,
+    /// The raw device type
+    pub device_type: u8,
+    /// The raw device status
+    pub status: u8,
+    /// The beta number of the device's firmware
+    pub beta_version: u8,
+    /// The device's firmware version
+    pub version: u16,
+    /// The device's bootloader version
+    pub boot_version: u16,
+}
+
+/// Reads the status of every device plugged into the brain
+pub fn get_device_status<T: Read + Write>(device: &mut VexDevice<T>) -> Result<Vec<DeviceStatus>> {
+    let data = extended_command(device, ExtendedCommand::GetDeviceStatus, Vec::new())?;
+    let mut reply = Reply::new(ExtendedCommand::GetDeviceStatus, &data);
+
+    // The reply is a count followed by an entry for each device
+    let count = reply.u8()?;
+    let mut devices = Vec::new();
+    for _ in 0..count {
+        devices.push(DeviceStatus {
+            port: reply.u8()?,
+            device_type: reply.u8()?,
+            status: reply.u8()?,
+            beta_version: reply.u8()?,
+            version: reply.u16()?,
+            boot_version: reply.u16()?,
+        });
+    }
+
+    Ok(devices)
+}
+
+
+/// A firmware version as major.minor.build and a beta number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub build: u8,
+    pub beta: u8,
+}
+
+impl std::fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)?;
+        if self.beta != 0 {
+            write!(f, "-b{}", self.beta)?;
+        }
+        Ok(())
+    }
+}
+
+// A beta of zero is a release, which comes after every beta of the same version
+impl Ord for FirmwareVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.major, self.minor, self.build, self.beta == 0, self.beta)
+            .cmp(&(other.major, other.minor, other.build, other.beta == 0, other.beta))
+    }
+}
+
+impl PartialOrd for FirmwareVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for FirmwareVersion {
+    type Err = anyhow::Error;
+
+    /// Parses versions of the form `1.1.2` or `1.1.2-b3`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid firmware version {:?}, expected something like 1.1.2 or 1.1.2-b3", s);
+
+        let (version, beta) = match s.trim().split_once("-b") {
+            Some((version, beta)) => (version, beta.parse().map_err(|_| invalid())?),
+            None => (s.trim(), 0),
+        };
+
+        let parts = version.split('.')
+            .map(|p| p.parse::<u8>())
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+
+        Ok(Self { major: parts[0], minor: parts[1], build: parts[2], beta })
+    }
+}
+
+impl FirmwareVersion {
+    fn read(reply: &mut Reply) -> Result<Self> {
+        let b = reply.bytes(4)?;
+        Ok(Self { major: b[0], minor: b[1], build: b[2], beta: b[3] })
+    }
+}
+
+/// The firmware versions reported by the brain
+#[derive(Clone, Copy, Debug)]
+pub struct SystemStatus {
+    /// The VEXos version
+    pub system_version: FirmwareVersion,
+    /// The firmware version of the user processor
+    pub cpu0_version: FirmwareVersion,
+    /// The firmware version of the system processor
+    pub cpu1_version: FirmwareVersion,
+    /// The firmware version of the NXP touch controller
+    pub nxp_version: u8,
+    /// The brain's unique id
+    pub system_id: u32,
+}
+
+/// Reads the firmware versions from the brain
+pub fn get_system_status<T: Read + Write>(device: &mut VexDevice<T>) -> Result<SystemStatus> {
+    let data = extended_command(device, ExtendedCommand::GetSystemStatus, Vec::new())?;
+    let mut reply = Reply::new(ExtendedCommand::GetSystemStatus, &data);
+
+    // Skip the leading reserved byte
+    reply.bytes(1)?;
+    let system_version = FirmwareVersion::read(&mut reply)?;
+    let cpu0_version = FirmwareVersion::read(&mut reply)?;
+    let cpu1_version = FirmwareVersion::read(&mut reply)?;
+
+    // Three more reserved bytes sit before the NXP version
+    reply.bytes(3)?;
+    let nxp_version = reply.u8()?;
+    let system_id = reply.u32()?;
+
+    Ok(SystemStatus { system_version, cpu0_version, cpu1_version, nxp_version, system_id })
+}
+
+
+/// The state of the brain's radio
+#[derive(Clone, Copy, Debug)]
+pub struct RadioStatus {
+    /// The kind of radio device, zero if there is none
+    pub device: u8,
+    /// The link quality
+    pub quality: u16,
+    /// The signal strength
+    pub strength: i16,
+    /// The channel the radio is on
+    pub channel: u8,
+    /// The timeslot the radio is using
+    pub timeslot: u8,
+}
+
+impl RadioStatus {
+    /// Returns true if the radio is linked to a controller
+    pub fn linked(&self) -> bool {
+        self.device != 0 && self.quality > 0
+    }
+}
+
+/// Reads the radio status from the brain
+pub fn get_radio_status<T: Read + Write>(device: &mut VexDevice<T>) -> Result<RadioStatus> {
+    let data = extended_command(device, ExtendedCommand::GetRadioStatus, Vec::new())?;
+    let mut reply = Reply::new(ExtendedCommand::GetRadioStatus, &data);
+
+    Ok(RadioStatus {
+        device: reply.u8()?,
+        quality: reply.u16()?,
+        strength: reply.i16()?,
+        channel: reply.u8()?,
+        timeslot: reply.u8()?,
+    })
+}
+
+
+/// The key the brain stores its name under
+pub const KEY_ROBOT_NAME: &str = "robotname";
+/// The key the brain stores its team number under
+pub const KEY_TEAM_NUMBER: &str = "teamnumber";
+
+/// Reads a value from the brain's key-value store
+pub fn kv_read<T: Read + Write>(device: &mut VexDevice<T>, key: &str) -> Result<String> {
+    // The key is sent null terminated
+    let mut payload = key.as_bytes().to_vec();
+    payload.push(0);
+
+    let data = extended_command(device, ExtendedCommand::ReadKeyValue, payload)?;
+
+    // And so is the value that comes back
+    let end = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).to_string())
+}
+
+/// Writes a value to the brain's key-value store
+pub fn kv_write<T: Read + Write>(device: &mut VexDevice<T>, key: &str, value: &str) -> Result<()> {
+    if !value.is_ascii() {
+        return Err(anyhow::anyhow!("{:?} can only contain ASCII characters", value));
+    }
+
+    // The key and value are both sent null terminated
+    let mut payload = key.as_bytes().to_vec();
+    payload.push(0);
+    payload.extend_from_slice(value.as_bytes());
+    payload.push(0);
+
+    extended_command(device, ExtendedCommand::WriteKeyValue, payload)?;
+
+    Ok(())
+}
+
+
+/// The radio channels a controller can use to talk to the brain
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RadioChannel {
+    /// The channel used for driving, which is slow for transfers
+    Pit,
+    /// The faster channel used for uploading and terminal output
+    #[default]
+    Download,
+}
+
+impl From<RadioChannel> for V5ControllerChannel {
+    fn from(channel: RadioChannel) -> Self {
+        match channel {
+            RadioChannel::Pit => V5ControllerChannel::PIT,
+            RadioChannel::Download => V5ControllerChannel::UPLOAD,
+        }
+    }
+}
+
+/// Switches the radio channel a controller uses to talk to the brain
+pub fn switch_channel<T: Read + Write>(device: &mut VexDevice<T>, channel: RadioChannel) -> Result<()> {
+    // Radio channels are the first file control group
+    let value = match channel {
+        RadioChannel::Pit => 0,
+        RadioChannel::Download => 1,
+    };
+    extended_command(device, ExtendedCommand::FileControl, vec![0x01, value])?;
+
+    Ok(())
+}
+
+
+/// The metadata of a file stored on the brain
+#[derive(Clone, Debug)]
+pub struct FileEntry {
+    pub name: String,
+    pub size: u32,
+    pub addr: u32,
+    pub crc: u32,
+    pub r#type: [u8; 4],
+    pub timestamp: u32,
+    pub version: u32,
+}
+
+/// Lists the files stored on the brain under the given vendor id
+pub fn list_files<T: Read + Write>(device: &mut VexDevice<T>, vid: u8) -> Result<Vec<FileEntry>> {
+    // Ask how many files there are
+    let data = extended_command(device, ExtendedCommand::GetDirectoryCount, vec![vid, 0])?;
+    let count = Reply::new(ExtendedCommand::GetDirectoryCount, &data).u16()?;
+
+    // The index is a single byte in the protocol, so files past the 256th can't be asked for
+    if usize::from(count) > usize::from(u8::MAX) + 1 {
+        return Err(anyhow::anyhow!("The brain has {} files, but only the first {} can be listed. Remove some files and try again.", count, usize::from(u8::MAX) + 1));
+    }
+
+    // And then read the metadata of each one
+    let mut files = Vec::new();
+    for index in 0..count {
+        let index = u8::try_from(index)?;
+        let data = extended_command(device, ExtendedCommand::GetMetadataByIndex, vec![index, 0])?;
+        let mut reply = Reply::new(ExtendedCommand::GetMetadataByIndex, &data);
+
+        // Skip the echoed index
+        reply.u8()?;
+        let size = reply.u32()?;
+        let addr = reply.u32()?;
+        let crc = reply.u32()?;
+        let mut r#type = [0u8; 4];
+        r#type.copy_from_slice(reply.bytes(4)?);
+        let timestamp = reply.u32()?;
+        let version = reply.u32()?;
+        let name = reply.string(24)?;
+
+        files.push(FileEntry { name, size, addr, crc, r#type, timestamp, version });
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> FirmwareVersion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_releases_and_betas() {
+        assert_eq!(version("1.1.2"), FirmwareVersion { major: 1, minor: 1, build: 2, beta: 0 });
+        assert_eq!(version("1.1.2-b3"), FirmwareVersion { major: 1, minor: 1, build: 2, beta: 3 });
+        assert_eq!(version("1.1.2-b3").to_string(), "1.1.2-b3");
+    }
+
+    #[test]
+    fn rejects_malformed_versions() {
+        for s in ["1.1", "1.1.2.3", "1.1.x", "1.1.2-b", "1.1.2-bx", "256.0.0"] {
+            assert!(s.parse::<FirmwareVersion>().is_err(), "{} should not parse", s);
+        }
+    }
+
+    #[test]
+    fn betas_come_before_their_release() {
+        assert!(version("1.1.2-b1") < version("1.1.2-b3"));
+        assert!(version("1.1.2-b3") < version("1.1.2"));
+        assert!(version("1.1.1") < version("1.1.2-b1"));
+        assert!(version("1.1.2") < version("1.2.0-b1"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use console::style;
+use serde::Serialize;
+
+use crate::output::{self, Message};
+
+
+/// The directory inside a project that holds the target spec and linker script
+pub const TARGET_DIR: &str = "build";
+
+/// The file name of the target spec
+pub const TARGET_SPEC_FILE: &str = "armv7a-vex-eabi.json";
+
+/// The file name of the linker script
+pub const LINKER_SCRIPT_FILE: &str = "v5.ld";
+
+/// The target spec for the V5 brain's Cortex-A9
+pub const TARGET_SPEC: &str = include_str!("../templates/armv7a-vex-eabi.json");
+
+/// The linker script that lays a user program out in the brain's memory
+pub const LINKER_SCRIPT: &str = include_str!("../templates/v5.ld");
+
+/// Options for managing the project's target files
+#[derive(clap::Args, Debug, Clone)]
+pub struct TargetOptions {
+    /// Write the bundled target spec and linker script into the project, replacing outdated copies
+    #[clap(long)]
+    pub update: bool,
+}
+
+/// How a project's copy of a bundled file compares to the bundled one
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileState {
+    /// The project has no copy
+    Missing,
+    /// The copy matches the one bundled with cargo-v5
+    Current,
+    /// The copy differs from the one bundled with cargo-v5
+    Outdated,
+}
+
+/// A file cargo-v5 bundles, and where the project keeps its copy
+#[derive(Serialize, Clone, Debug)]
+pub struct TargetFile {
+    pub path: PathBuf,
+    pub state: FileState,
+    #[serde(skip)]
+    pub bundled: &'static str,
+}
+
+/// Reads the `[build] target` from a project's .cargo/config.toml, if it is a target spec
+fn configured_target_spec(dir: &Path) -> Option<PathBuf> {
+    std::fs::read_to_string(dir.join(".cargo/config.toml")).ok()
+        .and_then(|config| config.parse::<toml::Value>().ok())
+        .and_then(|config| config.get("build")?.get("target")?.as_str().map(PathBuf::from))
+        .filter(|target| target.extension().is_some_and(|e| e == "json"))
+}
+
+/// Finds the target spec a project builds with
+pub fn find_target_spec(dir: &Path) -> Option<PathBuf> {
+    // Prefer whatever the cargo config points at
+    configured_target_spec(dir).map(|target| dir.join(target)).into_iter()
+        .chain([dir.join(TARGET_SPEC_FILE), dir.join(TARGET_DIR).join(TARGET_SPEC_FILE)])
+        .find(|path| path.exists())
+}
+
+/// Returns true if the project links with our linker script
+fn uses_linker_script(dir: &Path) -> bool {
+    dir.join(TARGET_DIR).join(LINKER_SCRIPT_FILE).exists()
+        || std::fs::read_to_string(dir.join(".cargo/config.toml"))
+            .map(|config| config.contains(LINKER_SCRIPT_FILE))
+            .unwrap_or(false)
+}
+
+/// Compares a copy of a file to the bundled one.
+/// Target specs are compared as JSON, so formatting changes don't count.
+fn compare(path: &Path, bundled: &str) -> FileState {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return FileState::Missing,
+    };
+
+    let same = if path.extension().is_some_and(|e| e == "json") {
+        match (serde_json::from_str::<serde_json::Value>(&contents), serde_json::from_str::<serde_json::Value>(bundled)) {
+            (Ok(copy), Ok(bundled)) => copy == bundled,
+            _ => false,
+        }
+    } else {
+        contents.replace("\r\n", "\n").trim_end() == bundled.trim_end()
+    };
+
+    if same { FileState::Current } else { FileState::Outdated }
+}
+
+/// Lists the target files a project should have, and how they compare to the bundled ones
+pub fn project_files(dir: &Path) -> Vec<TargetFile> {
+    let spec = find_target_spec(dir)
+        .or_else(|| configured_target_spec(dir).map(|target| dir.join(target)))
+        .unwrap_or_else(|| dir.join(TARGET_DIR).join(TARGET_SPEC_FILE));
+
+    let mut files = vec![(spec, TARGET_SPEC)];
+    if uses_linker_script(dir) {
+        files.push((dir.join(TARGET_DIR).join(LINKER_SCRIPT_FILE), LINKER_SCRIPT));
+    }
+
+    files.into_iter().map(|(path, bundled)| TargetFile {
+        state: compare(&path, bundled),
+        path,
+        bundled,
+    }).collect()
+}
+
+/// Warns about any of the project's target files that differ from the bundled ones
+pub fn warn_if_outdated(dir: &Path) {
+    for file in project_files(dir) {
+        if file.state == FileState::Outdated {
+            output::warn(&format!("{} differs from the one bundled with cargo-v5 {}. Run `cargo v5 target --update` to replace it.",
+                file.path.display(),
+                env!("CARGO_PKG_VERSION"),
+            ));
+        }
+    }
+}
+
+/// Shows whether the project's target files are up to date, and writes the bundled ones with `--update`
+pub fn target(dir: &Path, options: &TargetOptions) -> Result<()> {
+    let mut files = project_files(dir);
+
+    if options.update {
+        for file in files.iter_mut().filter(|f| f.state != FileState::Current) {
+            if let Some(parent) = file.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&file.path, file.bundled)?;
+            file.state = FileState::Current;
+
+            if !output::is_json() {
+                println!("{} {}", style("Wrote").green().bright(), file.path.display());
+            }
+        }
+    }
+
+    if output::is_json() {
+        output::emit(&Message::Report { command: "target", data: serde_json::to_value(&files)? });
+        return Ok(());
+    }
+
+    for file in &files {
+        let (mark, state) = match file.state {
+            FileState::Current => (output::pass_mark(), "up to date"),
+            FileState::Outdated => (output::warn_mark(), "outdated"),
+            FileState::Missing => (output::fail_mark(), "missing"),
+        };
+        println!("{} {} {}", mark, style(file.path.display()).bold(), state);
+    }
+    if files.iter().any(|f| f.state != FileState::Current) {
+        println!("{}", style("Run `cargo v5 target --update` to write the files bundled with cargo-v5.").black().bright());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use std::{io::{Read, Write}, time::{Duration, Instant}};
+
+use anyhow::Result;
+use console::style;
+use vexrs_serial::protocol::VexrsSerial;
+use vexv5_serial::device::VexDevice;
+
+use crate::{crash::CrashDecoder, output::{self, Message}, radio, system::{self, RadioChannel}};
+
+
+/// The exit status returned when the terminal times out.
+/// This is the same status that coreutils `timeout` uses.
+pub const TIMEOUT_STATUS: i32 = 124;
+
+/// How often to ask the brain whether the program is still running
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Options controlling how the terminal behaves and when it exits
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct TerminalOptions {
+    /// Write raw program output to stdout without any decorations
+    #[clap(long)]
+    pub headless: bool,
+    /// Exit with status 124 after this many seconds
+    #[clap(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+    /// Exit once a line containing this string is printed.
+    /// If the string is directly followed by a number, that number is used as the exit status.
+    #[clap(long, value_name = "STRING")]
+    pub until: Option<String>,
+    /// Exit once the brain reports that the program has stopped running
+    #[clap(long)]
+    pub exit_on_stop: bool,
+    /// The ELF file of the running program, used to symbolize panic and abort reports
+    #[clap(long, value_name = "PATH")]
+    pub elf: Option<String>,
+    /// The radio channel to use when connected through a controller
+    #[clap(long, value_enum, default_value_t = RadioChannel::Download)]
+    pub channel: RadioChannel,
+}
+
+/// The exit status used when the number after the sentinel can't be an exit status
+const BAD_SENTINEL_STATUS: i32 = 1;
+
+/// Checks a line of program output for the sentinel, returning the exit status it carries.
+/// A number too large to be an exit status gives [`BAD_SENTINEL_STATUS`] rather than wrapping to success.
+fn match_sentinel(line: &[u8], sentinel: &str) -> Option<i32> {
+    let line = String::from_utf8_lossy(line);
+    let index = line.find(sentinel)?;
+
+    // Parse an optional exit status directly after the sentinel
+    let status: String = line[index + sentinel.len()..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    if status.is_empty() {
+        return Some(0);
+    }
+
+    // Exit statuses only keep their lowest byte, so 256 would look like success
+    Some(status.parse::<u8>().map(i32::from).unwrap_or(BAD_SENTINEL_STATUS))
+}
+
+/// Returns true if the error is caused by a serial read timing out
+fn is_timeout(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<std::io::Error>(), Some(e) if e.kind() == std::io::ErrorKind::TimedOut)
+}
+
+/// Streams program output from the brain into the handler until an exit condition in the options is met.
+/// The handler is given each chunk of output and may return an exit status to stop streaming.
+pub fn stream<T, F>(device: &mut VexDevice<T>, options: &TerminalOptions, mut handler: F) -> Result<i32>
+where
+    T: Read + Write,
+    F: FnMut(&[u8]) -> Result<Option<i32>>,
+{
+    // Begin timer
+    let start = Instant::now();
+    let timeout = options.timeout.map(Duration::from_secs);
+
+    // Output arrives much faster on the download channel, but the pit channel can be chosen to keep driving
+    radio::with_channel(device, options.channel, |device| {
+
+        // The program may not have started yet when the terminal opens,
+        // so only treat it as stopped once we have seen it running.
+        let mut seen_running = false;
+
+        loop {
+            // Read output until it is time to check on the program again.
+            // The serial wrapper borrows the device, so it is recreated after each poll.
+            let poll_at = Instant::now() + STOP_POLL_INTERVAL;
+            let mut serial = VexrsSerial::new(&mut *device);
+
+            while !options.exit_on_stop || Instant::now() < poll_at {
+
+                // Exit if we have run out of time
+                if let Some(timeout) = timeout {
+                    if start.elapsed() >= timeout {
+                        return Ok(TIMEOUT_STATUS);
+                    }
+                }
+
+                // A read timing out just means the program has been quiet
+                let data_type = match serial.read_data() {
+                    Ok(data_type) => data_type,
+                    Err(e) => {
+                        let e: anyhow::Error = e.into();
+                        if is_timeout(&e) {
+                            continue;
+                        }
+                        return Err(e);
+                    }
+                };
+
+                // Hand printed data to the handler
+                if let vexrs_serial::data::DataType::Print(d) = data_type {
+                    if let Some(status) = handler(&d)? {
+                        return Ok(status);
+                    }
+                }
+            }
+
+            // Release the device so we can ask the brain what it is running
+            drop(serial);
+
+            let flags = system::get_system_flags(device)?;
+            if flags.current_program.is_some() {
+                seen_running = true;
+            } else if seen_running {
+                return Ok(0);
+            }
+        }
+    })
+}
+
+/// Opens a terminal connection to the program running on the brain.
+/// Returns the program's exit status once one of the exit conditions in the options is met.
+pub fn terminal<T: Read+Write>(device: &mut VexDevice<T>, options: &TerminalOptions) -> Result<i32> {
+
+    // Only decorate the output if we are attached to a person
+    let json = output::is_json();
+    let decorate = !options.headless && !json;
+    if decorate {
+        println!("{}", style("Opened terminal. Press Ctrl-C to exit.").black().bright());
+    }
+
+    let mut stdout = std::io::stdout();
+
+    // The current line of output, used to look for the sentinel
+    let mut line = Vec::<u8>::new();
+
+    // Decode crash reports if we know which program is running
+    let mut decoder = options.elf.as_ref().map(CrashDecoder::new);
+
+    let status = stream(device, options, |data| {
+        if json {
+            output::emit(&Message::Output { text: &String::from_utf8_lossy(data) });
+        } else {
+            stdout.write_all(data)?;
+            stdout.flush()?;
+        }
+
+        if let Some(decoder) = &mut decoder {
+            decoder.feed(data);
+        }
+
+        // Look for the sentinel at the end of each line
+        if let Some(sentinel) = &options.until {
+            for &byte in data {
+                if byte != b'\n' {
+                    line.push(byte);
+                    continue;
+                }
+
+                if let Some(status) = match_sentinel(&line, sentinel) {
+                    return Ok(Some(status));
+                }
+                line.clear();
+            }
+        }
+
+        Ok(None)
+    })?;
+
+    if decorate {
+        println!();
+        println!("{} {}", style("Terminal exited with status").black().bright(), style(status).bold());
+    }
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_lines_without_sentinel() {
+        assert_eq!(match_sentinel(b"still running", "DONE:"), None);
+    }
+
+    #[test]
+    fn reads_status_after_sentinel() {
+        assert_eq!(match_sentinel(b"DONE:", "DONE:"), Some(0));
+        assert_eq!(match_sentinel(b"tests DONE:3 of 4", "DONE:"), Some(3));
+        assert_eq!(match_sentinel(b"DONE:255", "DONE:"), Some(255));
+        assert_eq!(match_sentinel(b"DONE: 3", "DONE:"), Some(0));
+    }
+
+    #[test]
+    fn never_turns_large_status_into_success() {
+        assert_eq!(match_sentinel(b"DONE:256", "DONE:"), Some(BAD_SENTINEL_STATUS));
+        assert_eq!(match_sentinel(b"DONE:99999999999999999999", "DONE:"), Some(BAD_SENTINEL_STATUS));
+    }
+}
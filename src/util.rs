@@ -1,15 +1,10 @@
 use std::{io::{Read, Write}, time::Duration};
 
 use serialport::{SerialPortType, SerialPort};
-use vexv5_serial::{ports::{VexSerialInfo, VexSerialClass}, device::{V5FileHandle}};
-use console::style;
-use dialoguer::{
-    Select,
-    theme::ColorfulTheme
-};
-use indicatif::{ProgressBar, ProgressStyle};
+use vexv5_serial::{ports::{VexSerialInfo, VexSerialClass}, device::{V5FileHandle, VexDevice}};
 use anyhow::Result;
 
+use crate::{error::Error, output::{self, Message}, progress::{Reporter, TransferEvent}, system};
 
 
 #[derive(Clone, Debug)]
@@ -18,8 +13,8 @@ pub enum DevicePair {
     Double(VexSerialInfo, VexSerialInfo)
 }
 
-/// Finds which vex devices to use
-pub fn find_devices() -> Result<DevicePair> {
+/// Discovers every vex device and groups their ports into pairs
+pub fn discover_pairs() -> Result<Vec<DevicePair>> {
     // Try to find vex devices
     let devices = vexv5_serial::ports::discover_vex_ports()?;
 
@@ -51,88 +46,116 @@ pub fn find_devices() -> Result<DevicePair> {
     
     // If there are no devices, then error
     if pairs.is_empty() {
-        print!("{} ", style("Error:").red().bright());
-        println!("{}", style("No Vex devices found.").black().bright());
-        return Err(anyhow::anyhow!("No Vex devices found"));
+        return Err(Error::DeviceNotFound.into());
     }
 
-    // If there is only one device, then use it.
-    // If not, then ask which one to use
-    let device = if pairs.len() == 1 {
-        pairs[0].clone()
-    } else {
+    for pair in &pairs {
+        let (class, ports) = match pair {
+            DevicePair::Single(d) => (format!("{:?}", d.class), vec![d.port_info.port_name.as_str()]),
+            DevicePair::Double(d1, d2) => ("Brain".to_string(), vec![d1.port_info.port_name.as_str(), d2.port_info.port_name.as_str()]),
+        };
+        output::emit(&Message::DeviceFound { class, ports });
+    }
 
-        // Generate a list of selections (just differently formatted devices)
-        let mut pselect = Vec::<String>::new();
-        for pair in pairs.clone() {
-            if let DevicePair::Single(d1) = pair {
-                pselect.push(format!("{:?} port: {} ({})", d1.class, d1.port_info.port_name, match d1.port_info.port_type {
-                    SerialPortType::UsbPort(p) => {
-                        p.product.unwrap_or_else(||"".to_string())
-                    },
-                    _ => {
-                        "Unsupported Device".to_string()
-                    }
-                }));
-            } else if let DevicePair::Double(d1, d2) = pair {
-                pselect.push(format!("Vex Brain with ports {} and {}",
-                    d1.port_info.port_name,
-                    d2.port_info.port_name
-                ));
-            }
-            
+    Ok(pairs)
+}
+
+/// Describes a device pair by its ports, without talking to it
+pub fn describe_ports(pair: &DevicePair) -> String {
+    match pair {
+        DevicePair::Single(d1) => {
+            format!("{:?} port: {} ({})", d1.class, d1.port_info.port_name, match &d1.port_info.port_type {
+                SerialPortType::UsbPort(p) => {
+                    p.product.clone().unwrap_or_else(||"".to_string())
+                },
+                _ => {
+                    "Unsupported Device".to_string()
+                }
+            })
+        },
+        DevicePair::Double(d1, d2) => {
+            format!("Vex Brain with ports {} and {}",
+                d1.port_info.port_name,
+                d2.port_info.port_name
+            )
         }
+    }
+}
 
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .items(&pselect)
-            .default(0)
-            .with_prompt("Multiple Vex devices found. Please select which one to use:")
-            .interact()?;
+/// Puts a brain's name and team number in front of a description, so brains can be told apart
+pub fn with_identity(identity: Option<(String, String)>, description: String) -> String {
+    match identity {
+        Some((name, team)) if !team.is_empty() => format!("{} (team {}) - {}", name, team, description),
+        Some((name, _)) => format!("{} - {}", name, description),
+        None => description,
+    }
+}
 
-        pairs[selection].clone()
-    };
+/// Describes a device pair for the user, leading with the brain's name.
+/// This opens the device to ask for its name, so use [`describe_ports`] when it is already connected
+/// or shouldn't be disturbed.
+pub fn describe_pair(pair: &DevicePair) -> String {
+    with_identity(brain_identity(pair), describe_ports(pair))
+}
+
+/// Connects to a device to ask for the name and team number of its brain.
+/// Returns None if the device can't be asked, so it can still be listed without them.
+fn brain_identity(pair: &DevicePair) -> Option<(String, String)> {
+    // A lone user port can't answer system queries, and asking through an unpaired
+    // controller only waits for the radio to time out
+    if let DevicePair::Single(d) = pair {
+        if !matches!(d.class, VexSerialClass::System) {
+            return None;
+        }
+    }
 
-    Ok(device)
+    read_identity(&mut connect(pair.clone()).ok()?)
 }
 
+/// Reads the name and team number of the brain from an open connection.
+/// Returns None if the brain doesn't answer.
+pub fn read_identity<T: Read + Write>(device: &mut VexDevice<T>) -> Option<(String, String)> {
+    let name = system::kv_read(device, system::KEY_ROBOT_NAME).ok()?;
+    let team = system::kv_read(device, system::KEY_TEAM_NUMBER).unwrap_or_default();
+
+    Some((name, team))
+}
 
 /// A prepared device ready to be used
 type PreparedDevice = ((VexSerialInfo, Box<dyn SerialPort>), Option<(VexSerialInfo, Box<dyn SerialPort>)>);
 
+/// Connects to a device pair, ready to send commands
+pub fn connect(device: DevicePair) -> Result<VexDevice<Box<dyn SerialPort>>> {
+    let (system, user) = prepare_device(device)?;
+
+    Ok(VexDevice::new(system, user)?)
+}
+
+/// Opens the serial port of a vex device
+fn open_port(info: &VexSerialInfo) -> Result<Box<dyn SerialPort>> {
+    let name = &info.port_info.port_name;
+
+    serialport::new(name, 115200)
+        .parity(serialport::Parity::None)
+        .timeout(Duration::new(vexv5_serial::device::SERIAL_TIMEOUT_SECONDS, vexv5_serial::device::SERIAL_TIMEOUT_NS))
+        .stop_bits(serialport::StopBits::One).open()
+        .map_err(|e| match e.kind {
+            serialport::ErrorKind::Io(std::io::ErrorKind::PermissionDenied) => Error::PermissionDenied { port: Some(name.clone()) }.into(),
+            _ => anyhow::Error::from(e).context(format!("Could not open {}", name)),
+        })
+}
+
 /// Prepares a device for use
 pub fn prepare_device(device: DevicePair) -> Result<PreparedDevice> {
     let (mut system, mut user) = match device {
         DevicePair::Double(d1, d2) => {
-            (
-                (
-                    d1.clone(),
-                    serialport::new(d1.port_info.port_name, 115200)
-                    .parity(serialport::Parity::None)
-                    .timeout(Duration::new(vexv5_serial::device::SERIAL_TIMEOUT_SECONDS, vexv5_serial::device::SERIAL_TIMEOUT_NS))
-                    .stop_bits(serialport::StopBits::One).open()?
-                ),
-                Some(
-                    (
-                        d2.clone(),
-                        serialport::new(d2.port_info.port_name, 115200)
-                            .parity(serialport::Parity::None)
-                            .timeout(Duration::new(vexv5_serial::device::SERIAL_TIMEOUT_SECONDS, vexv5_serial::device::SERIAL_TIMEOUT_NS))
-                            .stop_bits(serialport::StopBits::One).open()?
-                    )
-                ),
-            )
+            let system = open_port(&d1)?;
+            let user = open_port(&d2)?;
+            ((d1, system), Some((d2, user)))
         },
         DevicePair::Single(d1) => {
-            (
-                (
-                    d1.clone(),
-                    serialport::new(d1.port_info.port_name, 115200)
-                        .parity(serialport::Parity::None)
-                        .timeout(Duration::new(vexv5_serial::device::SERIAL_TIMEOUT_SECONDS, vexv5_serial::device::SERIAL_TIMEOUT_NS))
-                        .stop_bits(serialport::StopBits::One).open()?
-                ),
-                None
-            )
+            let system = open_port(&d1)?;
+            ((d1, system), None)
         }
     };
 
@@ -145,11 +168,78 @@ pub fn prepare_device(device: DevicePair) -> Result<PreparedDevice> {
     Ok((system, user))
 }
 
+/// The highest program slot on the brain, counting from zero
+pub const MAX_SLOT: u8 = 7;
+
+/// The execute option flag that stops the running program instead of starting one
+pub const EXECUTE_STOP: u8 = 0x80;
+
+/// Reads the program slot from the slot file in the current directory,
+/// creating the file with slot 0 if it does not exist yet.
+pub fn read_slot() -> Result<u8> {
+    // Detect if the slot file exists
+    let slot_file = std::path::Path::new("slot");
+    if !slot_file.exists() {
+        // If it doesn't exist, create it, defaulting the slot number to 0
+        std::fs::write(slot_file, "0")?;
+    }
+
+    parse_slot_file(slot_file)
+}
+
+/// Reads the program slot from the slot file in the current directory if there is one,
+/// and otherwise uses slot 0 without creating the file.
+pub fn existing_slot() -> Result<u8> {
+    let slot_file = std::path::Path::new("slot");
+    if !slot_file.exists() {
+        return Ok(0);
+    }
+
+    parse_slot_file(slot_file)
+}
+
+/// Parses a slot file, checking that it holds a valid slot
+fn parse_slot_file(slot_file: &std::path::Path) -> Result<u8> {
+    // Read in the slot file, parsing its contents into an u8
+    let contents = std::fs::read_to_string(slot_file)?;
+    match contents.trim().parse::<u8>() {
+        Ok(slot) if slot <= MAX_SLOT => Ok(slot),
+        _ => Err(Error::InvalidConfig(format!(
+            "The slot file contains {:?}, but the slot must be a number from 0 to {}", contents.trim(), MAX_SLOT
+        )).into()),
+    }
+}
+
+/// Converts an ELF file into a raw binary that can be uploaded to the brain.
+/// Returns the path of the binary file.
+pub fn objcopy(file: &str) -> Result<String> {
+    // We expect arm-none-eabi-objcopy
+    // TODO: A good idea would be to implement an objcopy alternative in rust.
+    let mut command = std::process::Command::new("arm-none-eabi-objcopy");
+    command.arg("-O").arg("binary");
+    command.arg(file);
+
+    // Add the bin prefix
+    let upload_file = format!("{}.bin", file);
+    command.arg(upload_file.clone());
+
+    // Run the command
+    let result = command.output().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => Error::ToolchainMissing { tool: "arm-none-eabi-objcopy".to_string() }.into(),
+        _ => anyhow::Error::from(e),
+    })?;
+    if !result.status.success() {
+        return Err(anyhow::anyhow!("arm-none-eabi-objcopy failed: {}", String::from_utf8_lossy(&result.stderr).trim()));
+    }
+
+    Ok(upload_file)
+}
+
 /// Writes a vector up to the file length of data to the file. 
 /// Ignores any extra bytes at the end of the vector.
 /// Returns the ammount of data read
-/// Same as the function provided in vexv5_serial but it shows progress to the user.
-pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data: Vec<u8>) -> Result<usize> {
+/// Same as the function provided in vexv5_serial but it reports progress on `file` to `reporter`.
+pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data: Vec<u8>, file: &str, reporter: &dyn Reporter) -> Result<usize> {
 
     // Save the max size so it is easier to access
     // We want it to be 3/4 size so we do not have issues with packet headers
@@ -171,14 +261,6 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
 
     // We will be incrementing this variable so we know how much we have written
     let mut how_much: usize = 0;
-    
-    // Create the progress bar
-    let bar = ProgressBar::new(size.into());
-
-    // Style the progress bar
-    bar.set_style(ProgressStyle::default_bar()
-        .template("[{elapsed_precise}] {binary_bytes_per_sec} {bar:40.cyan/blue} {percent}% {bytes:>7}/{total_bytes:7} {msg}")?
-        .progress_chars("##-"));
 
     // Iterate over the file's length in steps of max_size
     // We will be writing each iteration.
@@ -199,37 +281,26 @@ pub fn write_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, data:
         // Write the payload to the file
         handle.write_some(handle.metadata.addr + i as u32, payload)?;
 
-        // Update the progress bar
-        bar.inc(packet_size.into());
-
         // Increment how_much by packet data so we know how much we
         // have written to the file
         how_much += packet_size as usize;
-    }
 
-    // Finalize the progress bar
-    bar.finish_and_clear();
+        // Report our progress
+        reporter.report(&TransferEvent::Progress { file, done: how_much as u64, total: size.into() });
+    }
 
     Ok(how_much)
 }
 
 /// Reads data from a file on the V5 device.
-/// Same as the function provided in vexv5_serial but it shows progress to the user.
-pub fn read_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>) -> Result<Vec<u8>> {
+/// Same as the function provided in vexv5_serial but it reports progress on `file` to `reporter`.
+pub fn read_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>, file: &str, reporter: &dyn Reporter) -> Result<Vec<u8>> {
     // Create the buffer to store data in
     let mut data = Vec::<u8>::new();
 
     let max_size: u16 = 512;
     let length = handle.transfer_metadata.file_size;
 
-    // Create the progress bar
-    let bar = ProgressBar::new(length.into());
-
-    // Style the progress bar
-    bar.set_style(ProgressStyle::default_bar()
-        .template("[{elapsed_precise}] {binary_bytes_per_sec} {bar:40.cyan/blue} {percent}% {bytes:>7}/{total_bytes:7} {msg}")?
-        .progress_chars("##-"));
-
 
     // Iterate over the file's size in steps of max_packet_size
     for i in (0..length).step_by(max_size.into()) {
@@ -244,14 +315,11 @@ pub fn read_file_progress<T: Read + Write>(handle: &mut V5FileHandle<T>) -> Resu
         // Read the data and append it to the buffer
         data.extend(handle.read_len(i+handle.metadata.addr, (packet_size + 3) & !3)?);
 
-        // Update the progress bar
-        bar.inc(packet_size.into());
+        // Report our progress
+        reporter.report(&TransferEvent::Progress { file, done: (i + <u32>::from(packet_size)).into(), total: length.into() });
     }
 
     let data = data[..length as usize].to_vec();
-
-    // Finalize the progress bar
-    bar.finish_and_clear();
     
     Ok(data)
 }
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+/// The header VEXos checks at the start of every program:
+/// the "XVX5" magic, a user program, owned by a partner, with no options.
+#[link_section = ".code_signature"]
+#[no_mangle]
+#[used]
+static CODE_SIGNATURE: [u32; 8] = [0x3558_5658, 0, 2, 0, 0, 0, 0, 0];
+
+extern "C" {
+    static mut __bss_start: u32;
+    static mut __bss_end: u32;
+}
+
+/// Where VEXos starts the program
+#[link_section = ".boot"]
+#[no_mangle]
+pub unsafe extern "C" fn _boot() -> ! {
+    // The brain doesn't clear memory between programs, so zero .bss ourselves
+    let mut bss = core::ptr::addr_of_mut!(__bss_start);
+    while bss < core::ptr::addr_of_mut!(__bss_end) {
+        bss.write_volatile(0);
+        bss = bss.add(1);
+    }
+
+    main()
+}
+
+fn main() -> ! {
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop {}
+}
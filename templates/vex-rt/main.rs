@@ -0,0 +1,15 @@
+#![no_std]
+#![no_main]
+
+use vex_rt::prelude::*;
+
+struct Bot;
+
+impl Robot for Bot {
+    fn new(_peripherals: Peripherals) -> Self {
+        println!("Hello from the brain!");
+        Bot
+    }
+}
+
+entry!(Bot);